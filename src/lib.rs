@@ -1,4 +1,13 @@
+#[cfg(feature = "encryption")]
+extern crate argon2;
+#[cfg(feature = "encryption")]
+extern crate chacha20poly1305;
 extern crate chrono;
+extern crate serde;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
+extern crate uuid;
 
 #[macro_use]
 extern crate serde_derive;
@@ -6,18 +15,179 @@ extern crate serde_derive;
 
 pub mod data
 {
-    pub use chrono::{ Utc, NaiveDate, Duration };
+    pub use chrono::{ Utc, Local, FixedOffset, NaiveDate, Datelike, Duration, Weekday };
+    pub use uuid::Uuid;
 
     #[derive(Debug, Deserialize)]
     pub struct VersionedSchedule
     {
-        pub tasks: Vec<VersionedTask>
+        pub tasks: Vec<VersionedTask>,
+
+        #[serde(default)]
+        pub archived: Vec<VersionedTask>
     }
 
     #[derive(Debug, Default, Serialize)]
     pub struct Schedule
     {
-        pub tasks: Vec<Task>
+        pub tasks: Vec<Task>,
+
+        /// Completed one-off tasks, kept for their history.
+        pub archived: Vec<Task>
+    }
+
+    impl Schedule
+    {
+        /// Checks that every task has a name, that no two tasks share one,
+        /// and that no task depends on itself, directly or not.
+        pub fn validate(&self) -> Result<(), super::Error>
+        {
+            use std::collections::HashSet;
+
+            if self.tasks.iter().any(|task| task.name.trim().is_empty())
+            {
+                return Err(super::Error::EmptyName);
+            }
+
+            let mut names = HashSet::new();
+            if let Some(task) = self.tasks.iter().find(|task| !names.insert(&task.name))
+            {
+                return Err(super::Error::DuplicateName(task.name.clone()));
+            }
+
+            for task in &self.tasks
+            {
+                let mut visited = HashSet::new();
+                let mut current = task;
+                while let Some(prerequisite) = current.depends_on.as_ref().and_then(|name| self.find(name))
+                {
+                    if prerequisite.name == task.name
+                    {
+                        return Err(super::Error::DependencyCycle(task.name.clone()));
+                    }
+                    if !visited.insert(&prerequisite.name)
+                    {
+                        break;
+                    }
+                    current = prerequisite;
+                }
+            }
+
+            Ok(())
+        }
+
+        /// The name of the task `task` depends on, if that hasn't been
+        /// completed since `task` last was. A prerequisite that's been
+        /// finished and archived still counts, and one that's gone entirely
+        /// blocks nothing.
+        pub fn blocked_by<'a>(&'a self, task: &Task) -> Option<&'a str>
+        {
+            let name = task.depends_on.as_ref()?;
+            let prerequisite = self.find(name).or_else(|| self.archived.iter().find(|t| &t.name == name))?;
+
+            let blocked = match (prerequisite.date_completed, task.date_completed)
+            {
+                (None, _) => true,
+                (Some(done), Some(last_done)) => done < last_done,
+                (Some(_), None) => false
+            };
+
+            match blocked
+            {
+                true => Some(&prerequisite.name),
+                false => None
+            }
+        }
+
+        /// Reads a schedule in the format given by the file extension,
+        /// upgrading any tasks saved by older versions.
+        pub fn load(path: &::std::path::Path) -> Result<Schedule, super::Error>
+        {
+            let contents = ::std::fs::read_to_string(path).map_err(|e| super::Error::Io(e.to_string()))?;
+            Schedule::parse(&contents, super::FileFormat::from_path(path))
+        }
+
+        pub fn parse(contents: &str, format: super::FileFormat) -> Result<Schedule, super::Error>
+        {
+            let schedule: VersionedSchedule = super::deserialize(contents, format)?;
+
+            let mut failed = Vec::new();
+            let mut upversion = |tasks: Vec<VersionedTask>| -> Vec<Task>
+            {
+                tasks.into_iter().filter_map(|task|
+                {
+                    let name = task.name().to_owned();
+                    let task = task.upversioned();
+                    if task.is_none()
+                    {
+                        failed.push(name);
+                    }
+                    task
+                }).collect()
+            };
+
+            let schedule = Schedule { tasks: upversion(schedule.tasks), archived: upversion(schedule.archived) };
+
+            match failed.is_empty()
+            {
+                true => Ok(schedule),
+                false => Err(super::Error::Upversion(failed))
+            }
+        }
+
+        /// Writes the schedule in the format given by the file extension,
+        /// keeping the previous contents in `<path>.bak`.
+        pub fn save(&self, path: &::std::path::Path) -> Result<(), super::Error>
+        {
+            super::write_file(path, self)
+        }
+
+        /// Adds a task, as long as it has a name and no other task shares it.
+        pub fn add_task(&mut self, task: Task) -> Result<(), super::Error>
+        {
+            if task.name.trim().is_empty()
+            {
+                return Err(super::Error::EmptyName);
+            }
+
+            if self.find(&task.name).is_some()
+            {
+                return Err(super::Error::DuplicateName(task.name));
+            }
+
+            self.tasks.push(task);
+            Ok(())
+        }
+
+        pub fn remove_task(&mut self, id: Uuid) -> Option<Task>
+        {
+            let index = self.tasks.iter().position(|task| task.id == id)?;
+            Some(self.tasks.remove(index))
+        }
+
+        /// Finds the first task with exactly this name.
+        pub fn find(&self, name: &str) -> Option<&Task>
+        {
+            self.tasks.iter().find(|task| task.name == name)
+        }
+
+        /// Maps each task name to its position in `tasks`, for repeated
+        /// lookups. Where names are duplicated the first task wins, as with `find`.
+        pub fn index(&self) -> ::std::collections::HashMap<&str, usize>
+        {
+            super::index_by_name(&self.tasks)
+        }
+    }
+
+    /// What happened to a task after it was completed.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum Outcome
+    {
+        /// The task repeats and is next due on this date.
+        Rescheduled(NaiveDate),
+
+        /// The task doesn't repeat, so it's finished with.
+        Finished
     }
 
     #[derive(Debug, Deserialize)]
@@ -31,11 +201,217 @@ pub mod data
     #[derive(Debug, Serialize, Deserialize)]
     pub struct Task
     {
+        /// Assigned when the task is added, or when it's first loaded for older files.
+        #[serde(default = "Uuid::new_v4")]
+        pub id: Uuid,
+
         pub name: String,
         pub date_completed: Option<Date>,
         pub date_due: Date,
         pub repeat: Repeat,
-        pub at_least: bool
+        pub at_least: bool,
+
+        /// The last day the task can be due. Completing it when the next due
+        /// date would be later finishes it, like a task that never repeats.
+        #[serde(default)]
+        pub repeat_until: Option<Date>,
+
+        /// How many more times the task needs doing. Completing it the last
+        /// time finishes it, like a task that never repeats.
+        #[serde(default)]
+        pub repeat_count: Option<u32>,
+
+        #[serde(default, deserialize_with = "deserialize_history")]
+        pub history: Vec<Completion>,
+
+        #[serde(default)]
+        pub tags: Vec<String>,
+
+        /// The one project the task belongs to, like "Kitchen".
+        #[serde(default)]
+        pub project: Option<String>,
+
+        /// The name of a task that needs doing before this one each time.
+        #[serde(default)]
+        pub depends_on: Option<String>,
+
+        #[serde(default)]
+        pub priority: u8,
+
+        #[serde(default)]
+        pub note: Option<String>,
+
+        #[serde(default = "default_active")]
+        pub active: bool
+    }
+
+    fn default_active() -> bool
+    {
+        true
+    }
+
+    impl Task
+    {
+        /// A new task, first due today.
+        pub fn new(name: &str, repeat: Repeat) -> Task
+        {
+            Task
+            {
+                id: Uuid::new_v4(),
+                name: name.to_owned(),
+                date_completed: None,
+                date_due: Local::today().naive_local().into(),
+                repeat,
+                at_least: false,
+                repeat_until: None,
+                repeat_count: None,
+                history: Vec::new(),
+                tags: Vec::new(),
+                project: None,
+                depends_on: None,
+                priority: 0,
+                note: None,
+                active: true
+            }
+        }
+
+        pub fn with_due(self, date_due: NaiveDate) -> Task
+        {
+            Task { date_due: date_due.into(), ..self }
+        }
+
+        pub fn with_at_least(self, at_least: bool) -> Task
+        {
+            Task { at_least, ..self }
+        }
+
+        pub fn with_repeat_until(self, repeat_until: Option<NaiveDate>) -> Task
+        {
+            Task { repeat_until: repeat_until.map(Into::into), ..self }
+        }
+
+        pub fn with_repeat_count(self, repeat_count: Option<u32>) -> Task
+        {
+            Task { repeat_count, ..self }
+        }
+
+        pub fn with_tags(self, tags: Vec<String>) -> Task
+        {
+            Task { tags, ..self }
+        }
+
+        pub fn with_project(self, project: Option<String>) -> Task
+        {
+            Task { project, ..self }
+        }
+
+        pub fn with_depends_on(self, depends_on: Option<String>) -> Task
+        {
+            Task { depends_on, ..self }
+        }
+
+        pub fn with_priority(self, priority: u8) -> Task
+        {
+            Task { priority, ..self }
+        }
+
+        pub fn with_note(self, note: Option<String>) -> Task
+        {
+            Task { note, ..self }
+        }
+
+        /// Completes the task on `date`, returning true if it's finished with
+        /// and should be removed from the schedule. If the next due date can't
+        /// be worked out the task is left as it was; use `complete` for the error.
+        pub fn mark_done(&mut self, date: NaiveDate) -> bool
+        {
+            self.complete(date) == Ok(Outcome::Finished)
+        }
+
+        /// Records a completion on `date` and works out when the task is next
+        /// due. Regular tasks stay on their schedule, so completing late skips
+        /// any occurrences missed in between. `at_least` tasks repeat from `date`.
+        pub fn complete(&mut self, date: NaiveDate) -> Result<Outcome, super::Error>
+        {
+            self.complete_from(date, false)
+        }
+
+        /// Like `complete`, but only advances a regular task by one period, so
+        /// the next missed occurrence is due straight away.
+        pub fn catch_up(&mut self, date: NaiveDate) -> Result<Outcome, super::Error>
+        {
+            self.complete_from(date, true)
+        }
+
+        fn complete_from(&mut self, date_completed: NaiveDate, catch_up: bool) -> Result<Outcome, super::Error>
+        {
+            let previous_date_due = self.date_due.as_naive();
+            let missed = super::periods_missed(previous_date_due, date_completed, self.repeat);
+
+            let (repeat_start, repeat_past) = match (self.at_least, catch_up && missed > 0)
+            {
+                (true, _) => (date_completed, date_completed),
+                (false, true) => (previous_date_due, previous_date_due),
+                (false, false) => (previous_date_due, date_completed)
+            };
+            let next_due_date = super::next_due_date(repeat_start, repeat_past, self.repeat)?
+                .filter(|&date| self.repeat_until.is_none_or(|until| date <= until.as_naive()));
+
+            self.history.push(Completion { date: date_completed.into(), was_due: Some(previous_date_due.into()) });
+            self.date_completed = Some(date_completed.into());
+
+            self.repeat_count = self.repeat_count.map(|count| count.saturating_sub(1));
+            let next_due_date = next_due_date.filter(|_| self.repeat_count != Some(0));
+
+            match next_due_date
+            {
+                Some(next_due_date) =>
+                {
+                    self.date_due = next_due_date.into();
+                    Ok(Outcome::Rescheduled(next_due_date))
+                },
+                None => Ok(Outcome::Finished)
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Completion
+    {
+        pub date: Date,
+
+        #[serde(default)]
+        pub was_due: Option<Date>
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(untagged)]
+    pub enum VersionedCompletion
+    {
+        Current(Completion),
+        DateOnly(Date)
+    }
+
+    impl VersionedCompletion
+    {
+        pub fn upversioned(self) -> Completion
+        {
+            match self
+            {
+                VersionedCompletion::Current(c) => c,
+                VersionedCompletion::DateOnly(date) => Completion { date, was_due: None }
+            }
+        }
+    }
+
+    fn deserialize_history<'de, D>(deserializer: D) -> Result<Vec<Completion>, D::Error>
+    where
+        D: ::serde::Deserializer<'de>
+    {
+        use serde::Deserialize;
+
+        let history: Vec<VersionedCompletion> = Vec::deserialize(deserializer)?;
+        Ok(history.into_iter().map(VersionedCompletion::upversioned).collect())
     }
 
     #[derive(Debug, Deserialize)]
@@ -48,6 +424,17 @@ pub mod data
 
     impl VersionedTask
     {
+        pub fn name(&self) -> &str
+        {
+            match *self
+            {
+                VersionedTask::Current(ref t) => &t.name,
+                VersionedTask::Version010(ref t) => &t.name
+            }
+        }
+
+        /// The task in the current format, or `None` if it can't be converted,
+        /// such as a 0.1.0 task that repeats every 0 days.
         pub fn upversioned(self) -> Option<Task>
         {
             match self
@@ -59,41 +446,65 @@ pub mod data
                     let (date_completed, date_due) = if let Some(last_completed) = last_completed
                     {
                         let completed = last_completed.as_naive();
+                        let date_due = super::next_due_date(completed, completed, repeat).unwrap_or(None);
 
-                        let date_due = match completed
-                        {
-                            Some(date) => super::next_due_date(date, date, repeat),
-                            None => None
-                        };
-
-                        let date_due = match date_due
-                        {
-                            Some(date) => date,
-                            None => return None
-                        };
-
-                        (completed.map(Into::into), date_due.into())
+                        (Some(last_completed), date_due?.into())
                     }
                     else
                     {
                         (None, Utc::today().naive_utc().into())
                     };
 
+                    let history = date_completed.iter().cloned().map(|date| Completion { date, was_due: None }).collect();
+
                     Some(Task
                     {
+                        id: Uuid::new_v4(),
                         name,
                         date_completed,
                         date_due,
                         repeat,
-                        at_least: false
+                        at_least: false,
+                        repeat_until: None,
+                        repeat_count: None,
+                        history,
+                        tags: Vec::new(),
+                        project: None,
+                        depends_on: None,
+                        priority: 0,
+                        note: None,
+                        active: true
                     })
                 }
             }
         }
     }
 
-    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum Repeat
+    {
+        Never,
+        Days(u32),
+        Weeks(u32),
+        Months(u32),
+        Years(u32),
+        Compound { years: u32, months: u32, weeks: u32, days: u32 },
+
+        /// The same day every month, or the last day of shorter months.
+        MonthlyOnDay(u8),
+
+        /// The `n`th `weekday` of every month, like the 2nd Tuesday. Where a
+        /// month has no 5th one, the last one that month is used instead.
+        NthWeekday { n: u8, weekday: Weekday },
+
+        /// A number of weekdays, skipping Saturdays and Sundays.
+        BusinessDays(u32)
+    }
+
+    /// The tagged representation (`Days: 5`) used by older schedule files.
+    // TODO: Remove this fallback in the next release
+    #[derive(Debug, Deserialize)]
+    enum TaggedRepeat
     {
         Never,
         Days(u32),
@@ -101,24 +512,140 @@ pub mod data
         Years(u32)
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct Date(String);
+    #[derive(Debug, Deserialize)]
+    #[serde(untagged)]
+    enum VersionedRepeat
+    {
+        Tagged(TaggedRepeat),
+        Compact(String)
+    }
+
+    impl ::serde::Serialize for Repeat
+    {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: ::serde::Serializer
+        {
+            serializer.collect_str(self)
+        }
+    }
+
+    impl<'de> ::serde::Deserialize<'de> for Repeat
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: ::serde::Deserializer<'de>
+        {
+            use serde::de::Error;
+
+            match VersionedRepeat::deserialize(deserializer)?
+            {
+                VersionedRepeat::Tagged(TaggedRepeat::Never) => Ok(Repeat::Never),
+                VersionedRepeat::Tagged(TaggedRepeat::Days(i)) => Ok(Repeat::Days(i)),
+                VersionedRepeat::Tagged(TaggedRepeat::Months(i)) => Ok(Repeat::Months(i)),
+                VersionedRepeat::Tagged(TaggedRepeat::Years(i)) => Ok(Repeat::Years(i)),
+                VersionedRepeat::Compact(string) => super::repeat_from_string(&string)
+                    .map_err(|e| D::Error::custom(format!("Invalid repeat '{}': {}", string, e)))
+            }
+        }
+    }
+
+    impl ::std::fmt::Display for Repeat
+    {
+        fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+        {
+            match *self
+            {
+                Repeat::Never => write!(f, "never"),
+                Repeat::Days(days) => write!(f, "{}d", days),
+                Repeat::Weeks(weeks) => write!(f, "{}w", weeks),
+                Repeat::Months(months) => write!(f, "{}m", months),
+                Repeat::Years(years) => write!(f, "{}y", years),
+                Repeat::MonthlyOnDay(day) => write!(f, "m{}", day),
+                Repeat::BusinessDays(days) => write!(f, "{}b", days),
+                Repeat::NthWeekday { n, weekday } =>
+                {
+                    let names = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+                    write!(f, "{}{}", n, names[weekday.num_days_from_monday() as usize])
+                },
+                Repeat::Compound { years, months, weeks, days } =>
+                {
+                    for &(count, unit) in &[(years, 'y'), (months, 'm'), (weeks, 'w'), (days, 'd')]
+                    {
+                        if count > 0
+                        {
+                            write!(f, "{}{}", count, unit)?;
+                        }
+                    }
+
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct Stats
+    {
+        pub completions: usize,
+        pub on_time: usize,
+        pub late: usize,
+        pub average_interval: Option<f64>,
+        pub median_interval: Option<f64>,
+        pub streak: usize
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum TaskStatus
+    {
+        Overdue(i64),
+        DueToday,
+        Upcoming(i64)
+    }
+
+    /// A calendar date, stored on disk as a `YYYY-MM-DD` string.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Date(NaiveDate);
 
     impl From<NaiveDate> for Date
     {
         fn from(date: NaiveDate) -> Date
         {
-            Date(format!("{}", date))
+            Date(date)
         }
     }
 
     impl Date
     {
-        pub fn as_naive(&self) -> Option<NaiveDate>
+        pub fn as_naive(&self) -> NaiveDate
+        {
+            self.0
+        }
+    }
+
+    impl ::serde::Serialize for Date
+    {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: ::serde::Serializer
+        {
+            serializer.collect_str(&self.0)
+        }
+    }
+
+    impl<'de> ::serde::Deserialize<'de> for Date
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: ::serde::Deserializer<'de>
         {
             use std::str::FromStr;
+            use serde::de::Error;
 
-            NaiveDate::from_str(&self.0).ok()
+            let string = String::deserialize(deserializer)?;
+            NaiveDate::from_str(&string)
+                .map(Date)
+                .map_err(|_| D::Error::custom(format!("Invalid date '{}': expected YYYY-MM-DD", string)))
         }
     }
 }
@@ -127,90 +654,1150 @@ pub mod data
 use data::*;
 
 
-pub fn repeat_from_string(string: &str) -> Result<Repeat, &'static str>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error
 {
-    const PARSE_ERROR: &str = "Expected a number";
-    const UNIT_ERROR: &str = "Expected a suffix (d, m, y) for days, months, or years";
+    InvalidRepeat(&'static str),
+    InvalidDate(&'static str),
+    InvalidTimezone,
+    UnsupportedRecurrence(Repeat),
+    DateOutOfRange,
+    EmptyName,
+    DuplicateName(String),
+    DependencyCycle(String),
+    Io(String),
+    /// A parse failure, with the line and column it happened at if known.
+    Parse(String, Option<(usize, usize)>),
+    Serialize(String),
+    Upversion(Vec<String>),
+    Encryption(&'static str)
+}
 
-    if string == "never"
+impl std::fmt::Display for Error
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
     {
-        return Ok(Repeat::Never);
+        match *self
+        {
+            Error::InvalidRepeat(reason) => write!(f, "{}", reason),
+            Error::InvalidDate(reason) => write!(f, "{}", reason),
+            Error::InvalidTimezone => write!(f, "Expected a timezone of local, utc, or an offset like +02:00"),
+            Error::UnsupportedRecurrence(repeat) => write!(f, "Can't repeat a task every {}", repeat),
+            Error::DateOutOfRange => write!(f, "Date is out of range"),
+            Error::EmptyName => write!(f, "A task has an empty name"),
+            Error::DuplicateName(ref name) => write!(f, "More than one task is named '{}'", name),
+            Error::DependencyCycle(ref name) => write!(f, "Task '{}' depends on itself through its prerequisites", name),
+            Error::Io(ref reason) => write!(f, "Failed to access file: {}", reason),
+            Error::Parse(ref reason, Some((line, column))) => write!(f, "Failed to parse file at line {}, column {}: {}", line, column, reason),
+            Error::Parse(ref reason, None) => write!(f, "Failed to parse file: {}", reason),
+            Error::Serialize(ref reason) => write!(f, "Failed to serialize: {}", reason),
+            Error::Encryption(reason) => write!(f, "{}", reason),
+            Error::Upversion(ref names) =>
+            {
+                let names: Vec<_> = names.iter().map(|name| format!("'{}'", name)).collect();
+                write!(f, "Failed to upversion old tasks in schedule: {}. You may have to manually recreate them.", names.join(", "))
+            }
+        }
     }
+}
 
-    let (count, unit) = string.split_at(string.len() - 1);
-    let count: u32 = match count.parse()
-    {
-        Ok(c) => c,
-        Err(_) => return Err(PARSE_ERROR)
-    };
+impl std::error::Error for Error {}
 
-    let repeat = match unit
-    {
-        "d" => Repeat::Days(count),
-        "m" => Repeat::Months(count),
-        "y" => Repeat::Years(count),
-        _ => return Err(UNIT_ERROR)
-    };
 
-    Ok(repeat)
+/// Maps each task name to its position in `tasks`. Where names are
+/// duplicated the first task wins.
+pub fn index_by_name(tasks: &[Task]) -> std::collections::HashMap<&str, usize>
+{
+    let mut index = std::collections::HashMap::with_capacity(tasks.len());
+    for (position, task) in tasks.iter().enumerate()
+    {
+        index.entry(task.name.as_str()).or_insert(position);
+    }
+    index
 }
 
-pub fn days_until_due(due_date: NaiveDate, today: NaiveDate) -> i64
+
+/// Lowercases `name` and strips accents from Latin letters, so that names
+/// can be compared loosely, e.g. "Café" and "CAFE" both fold to "cafe".
+pub fn fold_name(name: &str) -> String
 {
-    due_date.signed_duration_since(today).num_days() as i64
+    name.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c
+        {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+            'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+            'ď' | 'đ' => 'd',
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+            'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+            'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' => 'i',
+            'ł' | 'ĺ' | 'ļ' | 'ľ' => 'l',
+            'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+            'ŕ' | 'ŗ' | 'ř' => 'r',
+            'ś' | 'ŝ' | 'ş' | 'š' => 's',
+            'ţ' | 'ť' => 't',
+            'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+            'ý' | 'ÿ' => 'y',
+            'ź' | 'ż' | 'ž' => 'z',
+            c => c
+        })
+        .collect()
 }
 
 
-pub fn next_due_date(previous_date_due: NaiveDate, date_completed: NaiveDate, repeat: Repeat) -> Option<NaiveDate>
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileFormat
 {
-    use chrono::Datelike;
-    use Repeat::*;
-
-    let mut due_date = previous_date_due;
+    Yaml,
+    Json,
+    Toml
+}
 
-    while due_date <= date_completed
+impl FileFormat
+{
+    /// Picks a format from the file extension, defaulting to YAML.
+    pub fn from_path(path: &std::path::Path) -> FileFormat
     {
-        due_date = match repeat
+        match path.extension().and_then(|extension| extension.to_str())
         {
-            Never => return None,
-            Days(i) => due_date + Duration::days(i as i64),
-            Months(i) => {
-                let months = due_date.month0() + i;
-                due_date
-                    .with_year(due_date.year() + months as i32 / 12)
-                    .and_then(|d| d.with_month0(months % 12))
-                    .expect("TODO: something???")
-            },
-            Years(i) => due_date.with_year(due_date.year() + i as i32).expect("TODO: something?"),
-        };
+            Some("json") => FileFormat::Json,
+            Some("toml") => FileFormat::Toml,
+            _ => FileFormat::Yaml
+        }
     }
-
-    Some(due_date)
 }
 
-
+/// Parses `contents` in the given format. Anything that isn't valid YAML is
+/// also tried as TOML, for files without an extension like `~/.doq_config`.
+pub fn deserialize<T>(contents: &str, format: FileFormat) -> Result<T, Error>
+where for <'de>
+    T: serde::Deserialize<'de>
+{
+    // Both serde_yaml and serde_json end their messages with the location,
+    // which is reported separately
+    fn located(message: String, line: usize, column: usize) -> Error
+    {
+        let suffix = format!(" at line {} column {}", line, column);
+        let reason = message.strip_suffix(&suffix).unwrap_or(&message).to_owned();
+        Error::Parse(reason, Some((line, column)))
+    }
+
+    match format
+    {
+        FileFormat::Json => serde_json::from_str(contents).map_err(|e| match e.line()
+        {
+            0 => Error::Parse(e.to_string(), None),
+            line => located(e.to_string(), line, e.column())
+        }),
+        FileFormat::Toml => toml::from_str(contents).map_err(|e| Error::Parse(e.to_string(), None)),
+        FileFormat::Yaml => serde_yaml::from_str(contents)
+            .map_err(|e| match e.location()
+            {
+                Some(location) => located(e.to_string(), location.line(), location.column()),
+                None => Error::Parse(e.to_string(), None)
+            })
+            .or_else(|e| toml::from_str(contents).map_err(|_| e))
+    }
+}
+
+pub fn serialize<T: serde::Serialize>(data: &T, format: FileFormat) -> Result<String, Error>
+{
+    let result = match format
+    {
+        FileFormat::Yaml => serde_yaml::to_string(data).map(|s| s + "\n").map_err(|e| e.to_string()),
+        FileFormat::Json => serde_json::to_string_pretty(data).map(|s| s + "\n").map_err(|e| e.to_string()),
+        FileFormat::Toml => toml::to_string(data).map_err(|e| e.to_string())
+    };
+
+    result.map_err(Error::Serialize)
+}
+
+/// Writes to a temporary file alongside `path` and renames it into place, so
+/// a failed write never leaves a truncated file behind. The previous contents
+/// are kept in `<path>.bak`.
+pub fn write_file<T: serde::Serialize>(path: &std::path::Path, data: &T) -> Result<(), Error>
+{
+    write_bytes(path, serialize(data, FileFormat::from_path(path))?.as_bytes())
+}
+
+/// Writes `contents` to `path` atomically, keeping what was there before in a
+/// `.bak` file alongside it.
+pub fn write_bytes(path: &std::path::Path, contents: &[u8]) -> Result<(), Error>
+{
+    use std::fs::{ self, File };
+    use std::io::Write;
+
+    let file_name = path.file_name().ok_or_else(|| Error::Io("Invalid file path".into()))?.to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name));
+    let backup_path = backup_path(path);
+
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| match path.exists()
+        {
+            true => fs::copy(path, &backup_path).map(|_| ()),
+            false => Ok(())
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err()
+    {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result.map_err(|e| Error::Io(e.to_string()))
+}
+
+fn backup_path(path: &std::path::Path) -> std::path::PathBuf
+{
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.bak", file_name))
+}
+
+
+/// Encrypting schedules with a passphrase. Encrypted files start with `MAGIC`,
+/// so they can be told apart from plain ones even without the `encryption`
+/// feature, which the rest of this module needs.
+pub mod encryption
+{
+    /// Marks an encrypted file. It's followed by the salt, the nonce and the
+    /// encrypted contents.
+    pub const MAGIC: &[u8] = b"doq-encrypted-v1\n";
+
+    #[cfg(feature = "encryption")]
+    const SALT_LEN: usize = 16;
+
+    #[cfg(feature = "encryption")]
+    const NONCE_LEN: usize = 12;
+
+    pub fn is_encrypted(contents: &[u8]) -> bool
+    {
+        contents.starts_with(MAGIC)
+    }
+
+    /// True if the file at `path` exists and is encrypted.
+    pub fn is_encrypted_file(path: &::std::path::Path) -> bool
+    {
+        use std::io::Read;
+
+        let mut start = vec![0; MAGIC.len()];
+        ::std::fs::File::open(path)
+            .and_then(|mut file| file.read_exact(&mut start))
+            .is_ok_and(|_| is_encrypted(&start))
+    }
+
+    #[cfg(feature = "encryption")]
+    fn cipher(passphrase: &str, salt: &[u8]) -> Result<::chacha20poly1305::ChaCha20Poly1305, super::Error>
+    {
+        use chacha20poly1305::KeyInit;
+
+        let mut key = [0; 32];
+        ::argon2::Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|_| super::Error::Encryption("Failed to derive a key from the passphrase"))?;
+
+        Ok(::chacha20poly1305::ChaCha20Poly1305::new(&key.into()))
+    }
+
+    /// Encrypts `plaintext` with a key derived from `passphrase` and a fresh salt.
+    #[cfg(feature = "encryption")]
+    pub fn encrypt(plaintext: &str, passphrase: &str) -> Result<Vec<u8>, super::Error>
+    {
+        use chacha20poly1305::aead::{ Aead, AeadCore, OsRng };
+        use chacha20poly1305::aead::rand_core::RngCore;
+
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = ::chacha20poly1305::ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let ciphertext = cipher(passphrase, &salt)?.encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| super::Error::Encryption("Failed to encrypt the file"))?;
+
+        Ok([MAGIC, &salt, &nonce, &ciphertext].concat())
+    }
+
+    /// Decrypts contents written by `encrypt`.
+    #[cfg(feature = "encryption")]
+    pub fn decrypt(contents: &[u8], passphrase: &str) -> Result<String, super::Error>
+    {
+        use chacha20poly1305::aead::Aead;
+
+        const DECRYPT_ERROR: super::Error = super::Error::Encryption("Failed to decrypt the file. Is the passphrase right?");
+
+        let contents = contents.strip_prefix(MAGIC).ok_or(DECRYPT_ERROR)?;
+        if contents.len() < SALT_LEN + NONCE_LEN
+        {
+            return Err(DECRYPT_ERROR);
+        }
+
+        let (salt, rest) = contents.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let plaintext = cipher(passphrase, salt)?.decrypt(nonce.into(), ciphertext).map_err(|_| DECRYPT_ERROR)?;
+        String::from_utf8(plaintext).map_err(|_| DECRYPT_ERROR)
+    }
+
+    /// Serializes and encrypts `data`, then writes it like `write_file`. If
+    /// the file wasn't encrypted before, its plaintext backup is removed.
+    #[cfg(feature = "encryption")]
+    pub fn write_file<T: ::serde::Serialize>(path: &::std::path::Path, data: &T, passphrase: &str) -> Result<(), super::Error>
+    {
+        let contents = super::serialize(data, super::FileFormat::from_path(path))?;
+        let was_plaintext = path.exists() && !is_encrypted_file(path);
+
+        super::write_bytes(path, &encrypt(&contents, passphrase)?)?;
+
+        if was_plaintext
+        {
+            ::std::fs::remove_file(super::backup_path(path)).map_err(|e| super::Error::Io(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+
+pub fn repeat_from_string(string: &str) -> Result<Repeat, Error>
+{
+    const PARSE_ERROR: Error = Error::InvalidRepeat("Expected a number");
+    const ZERO_ERROR: Error = Error::InvalidRepeat("Expected a number greater than zero");
+    const UNIT_ERROR: Error = Error::InvalidRepeat("Expected a suffix (d, b, w, m, y) for days, business days, weeks, months, or years");
+
+    match string
+    {
+        "never" => return Ok(Repeat::Never),
+        "daily" => return Ok(Repeat::Days(1)),
+        "weekly" => return Ok(Repeat::Weeks(1)),
+        "biweekly" => return Ok(Repeat::Weeks(2)),
+        "monthly" => return Ok(Repeat::Months(1)),
+        "yearly" => return Ok(Repeat::Years(1)),
+        _ => ()
+    }
+
+    const DUPLICATE_ERROR: Error = Error::InvalidRepeat("Expected each suffix (d, w, m, y) at most once");
+    const DAY_ERROR: Error = Error::InvalidRepeat("Expected a day of the month from 1 to 31 after m");
+
+    if let Some(day) = string.strip_prefix('m')
+    {
+        return match day.parse()
+        {
+            Ok(day @ 1..=31) => Ok(Repeat::MonthlyOnDay(day)),
+            _ => Err(DAY_ERROR)
+        };
+    }
+
+    // A count then a day name, like "2tue", rather than a single unit letter
+    let digits = string.find(|c: char| !c.is_ascii_digit()).unwrap_or(string.len());
+    let (n, weekday) = string.split_at(digits);
+    if weekday.len() > 1 && weekday.chars().all(|c| c.is_ascii_alphabetic())
+    {
+        const WEEKDAY_ERROR: Error = Error::InvalidRepeat("Expected a day of the week after 1 to 5, like 2tue for the 2nd Tuesday of each month");
+
+        return match (n.parse(), weekday.parse())
+        {
+            (Ok(n @ 1..=5), Ok(weekday)) => Ok(Repeat::NthWeekday { n, weekday }),
+            _ => Err(WEEKDAY_ERROR)
+        };
+    }
+
+    if string.is_empty() || !string.is_ascii()
+    {
+        return Err(PARSE_ERROR);
+    }
+
+    // Split into segments like "1y", "6m", each a number followed by a unit
+    let mut segments = Vec::new();
+    let mut rest = string;
+
+    while !rest.is_empty()
+    {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or(UNIT_ERROR)?;
+        let (count, tail) = rest.split_at(digits);
+        let (unit, tail) = tail.split_at(1);
+        let count: u32 = count.parse().map_err(|_| PARSE_ERROR)?;
+        // A task repeating every 0 days would never move on
+        if count == 0
+        {
+            return Err(ZERO_ERROR);
+        }
+
+        segments.push((count, unit));
+        rest = tail;
+    }
+
+    let repeat = match *segments.as_slice()
+    {
+        [(count, "d")] => Repeat::Days(count),
+        [(count, "b")] => Repeat::BusinessDays(count),
+        [(count, "w")] => Repeat::Weeks(count),
+        [(count, "m")] => Repeat::Months(count),
+        [(count, "y")] => Repeat::Years(count),
+        [_] => return Err(UNIT_ERROR),
+        _ =>
+        {
+            let (mut years, mut months, mut weeks, mut days) = (None, None, None, None);
+
+            for &(count, unit) in &segments
+            {
+                let component = match unit
+                {
+                    "y" => &mut years,
+                    "m" => &mut months,
+                    "w" => &mut weeks,
+                    "d" => &mut days,
+                    _ => return Err(UNIT_ERROR)
+                };
+
+                if component.replace(count).is_some()
+                {
+                    return Err(DUPLICATE_ERROR);
+                }
+            }
+
+            Repeat::Compound
+            {
+                years: years.unwrap_or(0),
+                months: months.unwrap_or(0),
+                weeks: weeks.unwrap_or(0),
+                days: days.unwrap_or(0)
+            }
+        }
+    };
+
+    Ok(repeat)
+}
+
+pub fn date_from_string(string: &str, today: NaiveDate) -> Result<NaiveDate, Error>
+{
+    use std::str::FromStr;
+
+    const DATE_ERROR: Error = Error::InvalidDate("Expected a date (YYYY-MM-DD), an offset like +5d or -2w, or one of: today, tomorrow, yesterday");
+
+    match string
+    {
+        "today" => Ok(today),
+        "tomorrow" => Ok(today + Duration::days(1)),
+        "yesterday" => Ok(today - Duration::days(1)),
+        _ if string.starts_with('+') || string.starts_with('-') => offset_from_string(string, today),
+        _ => NaiveDate::from_str(string).map_err(|_| DATE_ERROR)
+    }
+}
+
+/// Applies an offset like `5d`, `+2w` or `-1m` to a date.
+pub fn offset_from_string(string: &str, date: NaiveDate) -> Result<NaiveDate, Error>
+{
+    const PARSE_ERROR: Error = Error::InvalidDate("Expected a number in offset");
+    const UNIT_ERROR: Error = Error::InvalidDate("Expected a suffix (d, w, m, y) for days, weeks, months, or years in offset");
+
+    let (sign, string) = match string.chars().next()
+    {
+        Some('+') => (1, &string[1..]),
+        Some('-') => (-1, &string[1..]),
+        _ => (1, string)
+    };
+
+    if string.len() < 2 || !string.is_ascii()
+    {
+        return Err(PARSE_ERROR);
+    }
+
+    let (count, unit) = string.split_at(string.len() - 1);
+    // parse would accept a second sign, as in "--5d"
+    if count.starts_with('+') || count.starts_with('-')
+    {
+        return Err(PARSE_ERROR);
+    }
+    let count = match count.parse::<i32>().map(|c| c.checked_mul(sign))
+    {
+        Ok(Some(c)) => c,
+        _ => return Err(PARSE_ERROR)
+    };
+
+    let date = match unit
+    {
+        "d" => date.checked_add_signed(Duration::days(count as i64)),
+        "w" => date.checked_add_signed(Duration::weeks(count as i64)),
+        "m" => add_months(date, count),
+        "y" => count.checked_mul(12).and_then(|months| add_months(date, months)),
+        _ => return Err(UNIT_ERROR)
+    };
+
+    date.ok_or(Error::DateOutOfRange)
+}
+
+/// Moves a date by a number of months, clamping to the end of the month if
+/// the day doesn't exist there (e.g. Jan 31 + 1 month is Feb 28).
+pub fn add_months(date: NaiveDate, months: i32) -> Option<NaiveDate>
+{
+    use chrono::Datelike;
+
+    let total = (date.year() * 12 + date.month0() as i32).checked_add(months)?;
+    let year = total.div_euclid(12);
+    let month = total.rem_euclid(12) as u32 + 1;
+
+    clamped_date(year, month, date.day())
+}
+
+/// Steps forward `days` weekdays from `date`, skipping weekends.
+fn add_business_days(mut date: NaiveDate, days: u32) -> Option<NaiveDate>
+{
+    use chrono::Datelike;
+
+    for _ in 0..days
+    {
+        date = date.succ_opt()?;
+        while date.weekday() == Weekday::Sat || date.weekday() == Weekday::Sun
+        {
+            date = date.succ_opt()?;
+        }
+    }
+
+    Some(date)
+}
+
+/// The `n`th `weekday` of a month, or the last one if there are fewer.
+fn nth_weekday(year: i32, month: u32, n: u8, weekday: Weekday) -> Option<NaiveDate>
+{
+    use chrono::Datelike;
+
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let offset = (weekday.num_days_from_monday() + 7 - first.weekday().num_days_from_monday()) % 7;
+    let mut day = 1 + offset + 7 * (n as u32 - 1);
+
+    loop
+    {
+        match NaiveDate::from_ymd_opt(year, month, day)
+        {
+            Some(date) => return Some(date),
+            None if day > 7 => day -= 7,
+            None => return None
+        }
+    }
+}
+
+/// The given day of a month, or the last day of the month if it's shorter.
+fn clamped_date(year: i32, month: u32, mut day: u32) -> Option<NaiveDate>
+{
+    loop
+    {
+        match NaiveDate::from_ymd_opt(year, month, day)
+        {
+            Some(date) => return Some(date),
+            None if day > 28 => day -= 1,
+            None => return None
+        }
+    }
+}
+
+/// Today's date in the given timezone: `local` (the default), `utc`, or a
+/// fixed offset like `+02:00` or `UTC-05:00`.
+pub fn today(timezone: Option<&str>) -> Result<NaiveDate, Error>
+{
+    const TIMEZONE_ERROR: Error = Error::InvalidTimezone;
+
+    let timezone = match timezone
+    {
+        None => return Ok(Local::today().naive_local()),
+        Some(timezone) => timezone.to_lowercase()
+    };
+
+    match timezone.as_str()
+    {
+        "local" => return Ok(Local::today().naive_local()),
+        "utc" => return Ok(Utc::today().naive_utc()),
+        _ => ()
+    }
+
+    let offset = timezone.trim_start_matches("utc");
+    let sign = match offset.chars().next()
+    {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(TIMEZONE_ERROR)
+    };
+
+    let mut parts = offset[1..].splitn(2, ':');
+    let hours: i32 = parts.next().and_then(|h| h.parse().ok()).ok_or(TIMEZONE_ERROR)?;
+    let minutes: i32 = match parts.next()
+    {
+        Some(m) => m.parse().map_err(|_| TIMEZONE_ERROR)?,
+        None => 0
+    };
+
+    if hours > 23 || minutes > 59
+    {
+        return Err(TIMEZONE_ERROR);
+    }
+
+    let offset = FixedOffset::east(sign * (hours * 3600 + minutes * 60));
+    Ok(Utc::now().with_timezone(&offset).date().naive_local())
+}
+
+pub fn days_until_due(due_date: NaiveDate, today: NaiveDate) -> i64
+{
+    due_date.signed_duration_since(today).num_days()
+}
+
+pub fn status(due_date: NaiveDate, today: NaiveDate) -> TaskStatus
+{
+    match days_until_due(due_date, today)
+    {
+        0 => TaskStatus::DueToday,
+        n if n > 0 => TaskStatus::Upcoming(n),
+        n => TaskStatus::Overdue(-n)
+    }
+}
+
+
+/// Summarises how consistently a task has been completed. Completions recorded
+/// without the date they were due are counted, but are neither on time nor late.
+pub fn stats(history: &[Completion]) -> Stats
+{
+    let mut completions: Vec<_> = history.iter().map(
+        |completion|
+        {
+            let date = completion.date.as_naive();
+            let on_time = completion.was_due.as_ref().map(Date::as_naive).map(|was_due| date <= was_due);
+            (date, on_time)
+        }).collect();
+    completions.sort_by_key(|&(date, _)| date);
+
+    let on_time = completions.iter().filter(|&&(_, on_time)| on_time == Some(true)).count();
+    let late = completions.iter().filter(|&&(_, on_time)| on_time == Some(false)).count();
+
+    let average_interval = match completions.len()
+    {
+        0 | 1 => None,
+        n => {
+            let first = completions[0].0;
+            let last = completions[n - 1].0;
+            Some(last.signed_duration_since(first).num_days() as f64 / (n - 1) as f64)
+        }
+    };
+
+    let mut intervals: Vec<i64> = completions.windows(2)
+        .map(|pair| pair[1].0.signed_duration_since(pair[0].0).num_days())
+        .collect();
+    intervals.sort_unstable();
+
+    let median_interval = match intervals.len()
+    {
+        0 => None,
+        n if n % 2 == 1 => Some(intervals[n / 2] as f64),
+        n => Some((intervals[n / 2 - 1] + intervals[n / 2]) as f64 / 2.0)
+    };
+
+    let streak = completions.iter().rev()
+        .filter_map(|&(_, on_time)| on_time)
+        .take_while(|&on_time| on_time)
+        .count();
+
+    Stats
+    {
+        completions: completions.len(),
+        on_time,
+        late,
+        average_interval,
+        median_interval,
+        streak
+    }
+}
+
+
+/// A field that differs between two copies of the same task.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldChange
+{
+    pub field: &'static str,
+    pub before: String,
+    pub after: String
+}
+
+
+/// Compares two copies of a task field by field, returning what changed from
+/// `before` to `after`.
+pub fn task_changes(before: &Task, after: &Task) -> Vec<FieldChange>
+{
+    fn describe_date(date: &Option<Date>) -> String
+    {
+        date.as_ref().map_or("never".to_owned(), |date| date.as_naive().to_string())
+    }
+
+    let fields = vec![
+        ("name", before.name.clone(), after.name.clone()),
+        ("date_due", before.date_due.as_naive().to_string(), after.date_due.as_naive().to_string()),
+        ("repeat", before.repeat.to_string(), after.repeat.to_string()),
+        ("at_least", before.at_least.to_string(), after.at_least.to_string()),
+        ("project", before.project.clone().unwrap_or_default(), after.project.clone().unwrap_or_default()),
+        ("depends_on", before.depends_on.clone().unwrap_or_default(), after.depends_on.clone().unwrap_or_default()),
+        ("date_completed", describe_date(&before.date_completed), describe_date(&after.date_completed)),
+        ("completions", before.history.len().to_string(), after.history.len().to_string()),
+        ("active", before.active.to_string(), after.active.to_string())
+    ];
+
+    fields.into_iter()
+        .filter(|(_, before, after)| before != after)
+        .map(|(field, before, after)| FieldChange { field, before, after })
+        .collect()
+}
+
+
+pub fn next_due_date(previous_date_due: NaiveDate, date_completed: NaiveDate, repeat: Repeat) -> Result<Option<NaiveDate>, Error>
+{
+    use chrono::Datelike;
+    use Repeat::*;
+
+    let mut due_date = previous_date_due;
+
+    while due_date <= date_completed
+    {
+        let next = match repeat
+        {
+            Never => return Ok(None),
+            Days(0) | Weeks(0) | Months(0) | Years(0) | MonthlyOnDay(0) | BusinessDays(0) => return Err(Error::UnsupportedRecurrence(repeat)),
+            MonthlyOnDay(day) if day > 31 => return Err(Error::UnsupportedRecurrence(repeat)),
+            NthWeekday { n, .. } if n == 0 || n > 5 => return Err(Error::UnsupportedRecurrence(repeat)),
+            NthWeekday { n, weekday } =>
+            {
+                match nth_weekday(due_date.year(), due_date.month(), n, weekday)
+                {
+                    Some(date) if date > due_date => Some(date),
+                    _ => add_months(due_date.with_day(1).unwrap(), 1)
+                        .and_then(|month| nth_weekday(month.year(), month.month(), n, weekday))
+                }
+            },
+            MonthlyOnDay(day) =>
+            {
+                // Later this month if the day hasn't passed yet, otherwise next month
+                match clamped_date(due_date.year(), due_date.month(), day as u32)
+                {
+                    Some(date) if date > due_date => Some(date),
+                    _ => add_months(due_date.with_day(1).unwrap(), 1)
+                        .and_then(|month| clamped_date(month.year(), month.month(), day as u32))
+                }
+            },
+            Days(i) => due_date.checked_add_signed(Duration::days(i as i64)),
+            Weeks(i) => due_date.checked_add_signed(Duration::weeks(i as i64)),
+            BusinessDays(i) => add_business_days(due_date, i),
+            Months(i) => add_months(due_date, i as i32),
+            Years(i) => (i as i32).checked_mul(12).and_then(|months| add_months(due_date, months)),
+            Compound { years: 0, months: 0, weeks: 0, days: 0 } => return Err(Error::UnsupportedRecurrence(repeat)),
+            Compound { years, months, weeks, days } =>
+            {
+                // Months and years first so end-of-month clamping applies, then weeks and days
+                (years as i32).checked_mul(12)
+                    .and_then(|total| total.checked_add(months as i32))
+                    .and_then(|total| add_months(due_date, total))
+                    .and_then(|date| date.checked_add_signed(Duration::weeks(weeks as i64)))
+                    .and_then(|date| date.checked_add_signed(Duration::days(days as i64)))
+            }
+        };
+
+        due_date = next.ok_or(Error::DateOutOfRange)?;
+    }
+
+    Ok(Some(due_date))
+}
+
+
+/// Counts the occurrences that fell due after `date_due` up to and including
+/// `date_completed`, which a single completion on that date skips over.
+pub fn periods_missed(date_due: NaiveDate, date_completed: NaiveDate, repeat: Repeat) -> u32
+{
+    occurrences(date_due, repeat)
+        .skip(1)
+        .take_while(|&date| date <= date_completed)
+        .count() as u32
+}
+
+
+/// Iterator over the successive due dates of a task, as returned by `occurrences`.
+#[derive(Debug, Clone)]
+pub struct Occurrences
+{
+    next: Option<NaiveDate>,
+    repeat: Repeat
+}
+
+impl Iterator for Occurrences
+{
+    type Item = NaiveDate;
+
+    fn next(&mut self) -> Option<NaiveDate>
+    {
+        let date = self.next?;
+        self.next = next_due_date(date, date, self.repeat).unwrap_or(None);
+        Some(date)
+    }
+}
+
+/// Yields `start` followed by each later due date, assuming every occurrence is
+/// completed on the day it's due. Ends after one date if the task doesn't
+/// repeat, or if the next date can't be calculated.
+pub fn occurrences(start: NaiveDate, repeat: Repeat) -> Occurrences
+{
+    Occurrences { next: Some(start), repeat }
+}
+
+/// Whether one of the `occurrences` from `start` lands on `date`.
+pub fn occurs_on(start: NaiveDate, repeat: Repeat, date: NaiveDate) -> bool
+{
+    occurrences(start, repeat).take_while(|&next| next <= date).any(|next| next == date)
+}
+
+
 #[cfg(test)]
+#[allow(clippy::zero_prefixed_literal)]
 mod tests
 {
+    extern crate serde_yaml;
+
     use super::*;
 
     #[test]
-    fn test_days_until_due()
+    fn test_days_until_due()
+    {
+        fn test(due: (i32, u32, u32), today: (i32, u32, u32)) -> i64
+        {
+            let (dy, dm, dd) = due;
+            let (ty, tm, td) = today;
+            days_until_due(NaiveDate::from_ymd(dy, dm, dd), NaiveDate::from_ymd(ty, tm, td))
+        }
+
+        assert_eq!(test((2017, 05, 27), (2017, 05, 27)), 0);
+        assert_eq!(test((2017, 05, 27), (2017, 05, 26)), 1);
+        assert_eq!(test((2017, 05, 27), (2017, 05, 28)), -1);
+        assert_eq!(test((2017, 04, 27), (2017, 05, 27)), -30);
+        assert_eq!(test((2017, 05, 27), (2017, 06, 27)), -31);
+        assert_eq!(test((2017, 05, 27), (2016, 05, 27)), 365);
+        assert_eq!(test((2016, 05, 27), (2015, 05, 27)), 366);
+    }
+
+
+    #[test]
+    fn test_repeat_display_round_trips()
+    {
+        for repeat in &[Repeat::Never, Repeat::Days(5), Repeat::Weeks(2), Repeat::Months(2), Repeat::Years(1), Repeat::Compound { years: 1, months: 6, weeks: 0, days: 0 }, Repeat::MonthlyOnDay(15), Repeat::NthWeekday { n: 2, weekday: Weekday::Tue }, Repeat::BusinessDays(3)]
+        {
+            assert_eq!(repeat_from_string(&repeat.to_string()), Ok(*repeat));
+        }
+    }
+
+
+    #[test]
+    fn test_repeat_aliases()
+    {
+        assert_eq!(repeat_from_string("daily"), Ok(Repeat::Days(1)));
+        assert_eq!(repeat_from_string("weekly"), repeat_from_string("1w"));
+        assert_eq!(repeat_from_string("biweekly"), Ok(Repeat::Weeks(2)));
+        assert_eq!(repeat_from_string("monthly"), Ok(Repeat::Months(1)));
+        assert_eq!(repeat_from_string("yearly"), Ok(Repeat::Years(1)));
+
+        let date = NaiveDate::from_ymd(2017, 05, 27);
+        assert_eq!(
+            next_due_date(date, date, repeat_from_string("weekly").unwrap()),
+            next_due_date(date, date, repeat_from_string("7d").unwrap()));
+    }
+
+
+    #[test]
+    fn test_monthly_on_day_repeat()
+    {
+        assert_eq!(repeat_from_string("m1"), Ok(Repeat::MonthlyOnDay(1)));
+        assert_eq!(repeat_from_string("m31"), Ok(Repeat::MonthlyOnDay(31)));
+        assert!(repeat_from_string("m0").is_err());
+        assert!(repeat_from_string("m32").is_err());
+        assert!(repeat_from_string("m").is_err());
+
+        let date = |y, m, d| NaiveDate::from_ymd(y, m, d);
+        let next = |due, completed, day| next_due_date(due, completed, Repeat::MonthlyOnDay(day)).unwrap().unwrap();
+
+        // Lands on the day regardless of when it was completed
+        assert_eq!(next(date(2017, 05, 01), date(2017, 05, 01), 1), date(2017, 06, 01));
+        assert_eq!(next(date(2017, 05, 01), date(2017, 05, 09), 1), date(2017, 06, 01));
+        assert_eq!(next(date(2017, 05, 10), date(2017, 05, 10), 15), date(2017, 05, 15));
+
+        // Clamped to the end of short months, without drifting afterwards
+        assert_eq!(next(date(2017, 01, 31), date(2017, 01, 31), 31), date(2017, 02, 28));
+        assert_eq!(next(date(2017, 02, 28), date(2017, 02, 28), 31), date(2017, 03, 31));
+        assert_eq!(next(date(2016, 01, 31), date(2016, 01, 31), 31), date(2016, 02, 29));
+        assert_eq!(next(date(2017, 03, 31), date(2017, 03, 31), 31), date(2017, 04, 30));
+        assert_eq!(next(date(2017, 12, 31), date(2017, 12, 31), 30), date(2018, 01, 30));
+
+        assert_eq!(next_due_date(date(2017, 05, 01), date(2017, 05, 01), Repeat::MonthlyOnDay(0)), Err(Error::UnsupportedRecurrence(Repeat::MonthlyOnDay(0))));
+    }
+
+
+    #[test]
+    fn test_nth_weekday_repeat()
     {
-        fn test(due: (i32, u32, u32), today: (i32, u32, u32)) -> i64
+        assert_eq!(repeat_from_string("2tue"), Ok(Repeat::NthWeekday { n: 2, weekday: Weekday::Tue }));
+        assert_eq!(repeat_from_string("1friday"), Ok(Repeat::NthWeekday { n: 1, weekday: Weekday::Fri }));
+        assert!(repeat_from_string("0tue").is_err());
+        assert!(repeat_from_string("6tue").is_err());
+        assert!(repeat_from_string("2tuesdays").is_err());
+        assert!(repeat_from_string("tue").is_err());
+
+        let date = |y, m, d| NaiveDate::from_ymd(y, m, d);
+        let next = |due, completed, n, weekday| next_due_date(due, completed, Repeat::NthWeekday { n, weekday }).unwrap().unwrap();
+
+        // The 2nd Tuesday of May 2017 is the 9th, then June 13th
+        assert_eq!(next(date(2017, 05, 01), date(2017, 05, 01), 2, Weekday::Tue), date(2017, 05, 09));
+        assert_eq!(next(date(2017, 05, 09), date(2017, 05, 09), 2, Weekday::Tue), date(2017, 06, 13));
+        assert_eq!(next(date(2017, 05, 09), date(2017, 06, 20), 2, Weekday::Tue), date(2017, 07, 11));
+
+        // May 2017 has five Mondays, but June only has four so the last is used
+        assert_eq!(next(date(2017, 05, 01), date(2017, 05, 01), 5, Weekday::Mon), date(2017, 05, 29));
+        assert_eq!(next(date(2017, 05, 29), date(2017, 05, 29), 5, Weekday::Mon), date(2017, 06, 26));
+        assert_eq!(next(date(2017, 06, 26), date(2017, 06, 26), 5, Weekday::Mon), date(2017, 07, 31));
+    }
+
+
+    #[test]
+    fn test_business_days_repeat()
+    {
+        assert_eq!(repeat_from_string("3b"), Ok(Repeat::BusinessDays(3)));
+        assert!(repeat_from_string("1w3b").is_err());
+        assert!(repeat_from_string("0b").is_err());
+
+        let date = |d| NaiveDate::from_ymd(2017, 05, d);
+        let next = |due, completed, days| next_due_date(due, completed, Repeat::BusinessDays(days)).unwrap().unwrap();
+
+        // Wednesday the 3rd, then across the weekend
+        assert_eq!(next(date(3), date(3), 1), date(4));
+        assert_eq!(next(date(3), date(3), 3), date(8));
+        assert_eq!(next(date(5), date(5), 1), date(8));
+        assert_eq!(next(date(3), date(3), 5), date(10));
+
+        // Starting on a weekend counts from the Monday
+        assert_eq!(next(date(6), date(6), 1), date(8));
+
+        // Late completions skip missed occurrences
+        assert_eq!(next(date(3), date(9), 3), date(11));
+
+        assert_eq!(next_due_date(date(3), date(3), Repeat::BusinessDays(0)), Err(Error::UnsupportedRecurrence(Repeat::BusinessDays(0))));
+    }
+
+
+    #[test]
+    fn test_compound_repeat()
+    {
+        assert_eq!(repeat_from_string("1y6m"), Ok(Repeat::Compound { years: 1, months: 6, weeks: 0, days: 0 }));
+        assert_eq!(repeat_from_string("2w3d"), Ok(Repeat::Compound { years: 0, months: 0, weeks: 2, days: 3 }));
+        assert_eq!(repeat_from_string("3d2w").unwrap().to_string(), "2w3d");
+        assert!(repeat_from_string("1d2d").is_err());
+        assert!(repeat_from_string("1y6").is_err());
+        assert!(repeat_from_string("1y6x").is_err());
+        assert!(repeat_from_string("0y0m").is_err());
+
+        let date = NaiveDate::from_ymd(2017, 08, 31);
+        let next = |repeat| next_due_date(date, date, repeat_from_string(repeat).unwrap()).unwrap();
+
+        assert_eq!(next("1y6m"), Some(NaiveDate::from_ymd(2019, 02, 28)));
+        assert_eq!(next("2w3d"), Some(NaiveDate::from_ymd(2017, 09, 17)));
+        assert_eq!(next("1m1d"), Some(NaiveDate::from_ymd(2017, 10, 01)));
+    }
+
+
+    #[test]
+    fn test_repeat_serde()
+    {
+        for (repeat, yaml) in &[(Repeat::Never, "never"), (Repeat::Days(5), "5d"), (Repeat::Months(2), "2m"), (Repeat::Years(1), "1y")]
         {
-            let (dy, dm, dd) = due;
-            let (ty, tm, td) = today;
-            days_until_due(NaiveDate::from_ymd(dy, dm, dd), NaiveDate::from_ymd(ty, tm, td))
+            assert!(serde_yaml::to_string(repeat).unwrap().contains(yaml));
+            assert_eq!(serde_yaml::from_str::<Repeat>(yaml).unwrap(), *repeat);
         }
 
-        assert_eq!(test((2017, 05, 27), (2017, 05, 27)), 0);
-        assert_eq!(test((2017, 05, 27), (2017, 05, 26)), 1);
-        assert_eq!(test((2017, 05, 27), (2017, 05, 28)), -1);
-        assert_eq!(test((2017, 04, 27), (2017, 05, 27)), -30);
-        assert_eq!(test((2017, 05, 27), (2017, 06, 27)), -31);
-        assert_eq!(test((2017, 05, 27), (2016, 05, 27)), 365);
-        assert_eq!(test((2016, 05, 27), (2015, 05, 27)), 366);
+        assert_eq!(serde_yaml::from_str::<Repeat>("Never").unwrap(), Repeat::Never);
+        assert_eq!(serde_yaml::from_str::<Repeat>("Days: 5").unwrap(), Repeat::Days(5));
+        assert_eq!(serde_yaml::from_str::<Repeat>("Years: 1").unwrap(), Repeat::Years(1));
+        assert!(serde_yaml::from_str::<Repeat>("5x").is_err());
+    }
+
+
+    #[test]
+    fn test_stats()
+    {
+        fn completion(date: (i32, u32, u32), was_due: (i32, u32, u32)) -> Completion
+        {
+            Completion
+            {
+                date: NaiveDate::from_ymd(date.0, date.1, date.2).into(),
+                was_due: Some(NaiveDate::from_ymd(was_due.0, was_due.1, was_due.2).into())
+            }
+        }
+
+        let empty = stats(&[]);
+        assert_eq!(empty.completions, 0);
+        assert_eq!(empty.average_interval, None);
+        assert_eq!(empty.median_interval, None);
+        assert_eq!(empty.streak, 0);
+
+        let history = vec![
+            completion((2017, 05, 01), (2017, 05, 01)),
+            completion((2017, 05, 09), (2017, 05, 08)),
+            completion((2017, 05, 15), (2017, 05, 16)),
+            completion((2017, 05, 22), (2017, 05, 23)),
+        ];
+
+        let stats = stats(&history);
+        assert_eq!(stats.completions, 4);
+        assert_eq!(stats.on_time, 3);
+        assert_eq!(stats.late, 1);
+        assert_eq!(stats.average_interval, Some(7.0));
+        assert_eq!(stats.median_interval, Some(7.0));
+        assert_eq!(stats.streak, 2);
+
+        let history = vec![
+            completion((2017, 05, 01), (2017, 05, 01)),
+            completion((2017, 05, 02), (2017, 05, 02)),
+            completion((2017, 05, 12), (2017, 05, 12)),
+        ];
+        assert_eq!(super::stats(&history).average_interval, Some(5.5));
+        assert_eq!(super::stats(&history).median_interval, Some(5.5));
+
+        let history = vec![
+            completion((2017, 05, 01), (2017, 05, 01)),
+            completion((2017, 05, 02), (2017, 05, 02)),
+            completion((2017, 05, 04), (2017, 05, 04)),
+            completion((2017, 05, 14), (2017, 05, 14)),
+        ];
+        assert_eq!(super::stats(&history).median_interval, Some(2.0));
+    }
+
+
+    #[test]
+    fn test_today()
+    {
+        assert_eq!(today(None), Ok(Local::today().naive_local()));
+        assert_eq!(today(Some("local")), Ok(Local::today().naive_local()));
+        assert_eq!(today(Some("UTC")), Ok(Utc::today().naive_utc()));
+        assert_eq!(today(Some("+00:00")), Ok(Utc::today().naive_utc()));
+        assert_eq!(today(Some("utc-0")), Ok(Utc::today().naive_utc()));
+        assert!(today(Some("+05:30")).is_ok());
+
+        assert!(today(Some("Europe/London")).is_err());
+        assert!(today(Some("+25:00")).is_err());
+        assert!(today(Some("+01:xx")).is_err());
+    }
+
+
+    #[test]
+    fn test_status()
+    {
+        let today = NaiveDate::from_ymd(2017, 05, 27);
+
+        assert_eq!(status(NaiveDate::from_ymd(2017, 05, 27), today), TaskStatus::DueToday);
+        assert_eq!(status(NaiveDate::from_ymd(2017, 05, 28), today), TaskStatus::Upcoming(1));
+        assert_eq!(status(NaiveDate::from_ymd(2017, 06, 27), today), TaskStatus::Upcoming(31));
+        assert_eq!(status(NaiveDate::from_ymd(2017, 05, 26), today), TaskStatus::Overdue(1));
+        assert_eq!(status(NaiveDate::from_ymd(2016, 05, 27), today), TaskStatus::Overdue(365));
+    }
+
+
+    #[test]
+    fn test_date_from_string()
+    {
+        let today = NaiveDate::from_ymd(2017, 05, 27);
+
+        assert_eq!(date_from_string("today", today), Ok(today));
+        assert_eq!(date_from_string("tomorrow", today), Ok(today + Duration::days(1)));
+        assert_eq!(date_from_string("yesterday", today), Ok(today - Duration::days(1)));
+        assert_eq!(date_from_string("tomorrow", NaiveDate::from_ymd(2017, 12, 31)),
+            Ok(NaiveDate::from_ymd(2018, 01, 01)));
+
+        assert_eq!(date_from_string("2017-06-01", today), Ok(NaiveDate::from_ymd(2017, 06, 01)));
+        assert!(date_from_string("someday", today).is_err());
+        assert!(date_from_string("2017-13-01", today).is_err());
+    }
+
+
+    #[test]
+    fn test_date_from_string_offsets()
+    {
+        let today = NaiveDate::from_ymd(2017, 05, 27);
+
+        assert_eq!(date_from_string("+5d", today), Ok(NaiveDate::from_ymd(2017, 06, 01)));
+        assert_eq!(date_from_string("-3d", today), Ok(NaiveDate::from_ymd(2017, 05, 24)));
+        assert_eq!(date_from_string("+2w", today), Ok(NaiveDate::from_ymd(2017, 06, 10)));
+        assert_eq!(date_from_string("-2w", today), Ok(NaiveDate::from_ymd(2017, 05, 13)));
+        assert_eq!(date_from_string("+1m", today), Ok(NaiveDate::from_ymd(2017, 06, 27)));
+        assert_eq!(date_from_string("-6m", today), Ok(NaiveDate::from_ymd(2016, 11, 27)));
+        assert_eq!(date_from_string("+1y", today), Ok(NaiveDate::from_ymd(2018, 05, 27)));
+
+        // Clamped to the end of shorter months
+        assert_eq!(date_from_string("+1m", NaiveDate::from_ymd(2017, 01, 31)),
+            Ok(NaiveDate::from_ymd(2017, 02, 28)));
+        assert_eq!(date_from_string("+1m", NaiveDate::from_ymd(2016, 01, 31)),
+            Ok(NaiveDate::from_ymd(2016, 02, 29)));
+        assert_eq!(date_from_string("-1m", NaiveDate::from_ymd(2017, 03, 31)),
+            Ok(NaiveDate::from_ymd(2017, 02, 28)));
+        assert_eq!(date_from_string("+1y", NaiveDate::from_ymd(2016, 02, 29)),
+            Ok(NaiveDate::from_ymd(2017, 02, 28)));
+
+        assert!(date_from_string("+5x", today).is_err());
+        assert!(date_from_string("+d", today).is_err());
+        assert!(date_from_string("+", today).is_err());
+        assert!(date_from_string("--5d", today).is_err());
+        assert!(date_from_string("+-5d", today).is_err());
+        assert!(date_from_string("--2147483648d", today).is_err());
+    }
+
+
+    #[test]
+    fn test_offset_from_string()
+    {
+        let date = NaiveDate::from_ymd(2017, 05, 27);
+
+        assert_eq!(offset_from_string("3d", date), Ok(NaiveDate::from_ymd(2017, 05, 30)));
+        assert_eq!(offset_from_string("+1w", date), Ok(NaiveDate::from_ymd(2017, 06, 03)));
+        assert_eq!(offset_from_string("2m", date), Ok(NaiveDate::from_ymd(2017, 07, 27)));
+        assert_eq!(offset_from_string("-1y", date), Ok(NaiveDate::from_ymd(2016, 05, 27)));
+
+        assert!(offset_from_string("d", date).is_err());
+        assert!(offset_from_string("3", date).is_err());
+        assert!(offset_from_string("3x", date).is_err());
+    }
+
+
+    #[test]
+    fn test_task_without_optional_fields_loads()
+    {
+        let yaml = "name: dishes\ndate_completed: ~\ndate_due: \"2017-05-27\"\nrepeat:\n  Days: 1\nat_least: false\n";
+        let task: Task = serde_yaml::from_str(yaml).unwrap();
+
+        assert!(task.history.is_empty());
+        assert!(task.tags.is_empty());
+        assert_eq!(task.priority, 0);
+        assert!(task.note.is_none());
+        assert!(task.active);
+        assert!(!task.id.is_nil());
+    }
+
+
+    #[test]
+    fn test_date_serde()
+    {
+        let date: Date = serde_yaml::from_str("\"2017-05-27\"").unwrap();
+
+        assert_eq!(date.as_naive(), NaiveDate::from_ymd(2017, 05, 27));
+        assert!(serde_yaml::to_string(&date).unwrap().contains("2017-05-27"));
+        assert!(serde_yaml::from_str::<Date>("\"2017-02-30\"").is_err());
+        assert!(serde_yaml::from_str::<Date>("\"tomorrow\"").is_err());
+    }
+
+
+    #[test]
+    fn test_date_only_history_loads()
+    {
+        let yaml = "name: dishes\ndate_completed: \"2017-05-26\"\ndate_due: \"2017-05-27\"\nrepeat:\n  Days: 1\nat_least: false\nhistory:\n  - \"2017-05-26\"\n";
+        let task: Task = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(task.history.len(), 1);
+        assert_eq!(task.history[0].date.as_naive(), NaiveDate::from_ymd(2017, 05, 26));
+        assert!(task.history[0].was_due.is_none());
     }
 
 
@@ -221,7 +1808,7 @@ mod tests
         {
             let (dy, dm, dd) = due;
             let (ty, tm, td) = completed;
-            next_due_date(NaiveDate::from_ymd(dy, dm, dd), NaiveDate::from_ymd(ty, tm, td), repeat)
+            next_due_date(NaiveDate::from_ymd(dy, dm, dd), NaiveDate::from_ymd(ty, tm, td), repeat).unwrap()
         }
 
         use Repeat::*;
@@ -282,4 +1869,363 @@ mod tests
         assert_eq!(test((2017, 05, 30), (2017, 05, 27), Days(1)),
             Some(NaiveDate::from_ymd(2017, 05, 30)));
     }
+
+
+    #[test]
+    fn test_schedule_validate()
+    {
+        let task = |name: &str|
+        {
+            let yaml = format!("name: \"{}\"\ndate_completed: ~\ndate_due: \"2017-05-27\"\nrepeat: 1d\nat_least: false\n", name);
+            serde_yaml::from_str::<Task>(&yaml).unwrap()
+        };
+
+        let schedule = Schedule { tasks: vec![task("dishes"), task("laundry")], archived: vec![task("dishes")] };
+        assert_eq!(schedule.validate(), Ok(()));
+
+        let schedule = Schedule { tasks: vec![task("dishes"), task("laundry"), task("dishes")], archived: Vec::new() };
+        assert_eq!(schedule.validate(), Err(Error::DuplicateName("dishes".to_owned())));
+
+        let schedule = Schedule { tasks: vec![task("dishes"), task("  ")], archived: Vec::new() };
+        assert_eq!(schedule.validate(), Err(Error::EmptyName));
+
+        let depends = |name: &str, on: &str| Task { depends_on: Some(on.to_owned()), ..task(name) };
+
+        let schedule = Schedule { tasks: vec![depends("dishes", "laundry"), depends("laundry", "plants"), task("plants")], archived: Vec::new() };
+        assert_eq!(schedule.validate(), Ok(()));
+
+        let schedule = Schedule { tasks: vec![depends("dishes", "laundry"), depends("laundry", "dishes")], archived: Vec::new() };
+        assert_eq!(schedule.validate(), Err(Error::DependencyCycle("dishes".to_owned())));
+
+        let schedule = Schedule { tasks: vec![task("dishes"), depends("laundry", "plants"), depends("plants", "plants")], archived: Vec::new() };
+        assert_eq!(schedule.validate(), Err(Error::DependencyCycle("plants".to_owned())));
+    }
+
+
+    #[test]
+    fn test_next_due_date_errors()
+    {
+        let date = NaiveDate::from_ymd(2017, 05, 27);
+
+        assert_eq!(next_due_date(date, date, Repeat::Days(0)), Err(Error::UnsupportedRecurrence(Repeat::Days(0))));
+        assert_eq!(next_due_date(date, date, Repeat::Months(0)), Err(Error::UnsupportedRecurrence(Repeat::Months(0))));
+        assert_eq!(next_due_date(date, date, Repeat::Years(1_000_000)), Err(Error::DateOutOfRange));
+    }
+
+
+    #[test]
+    fn test_occurrences()
+    {
+        fn test(start: (i32, u32, u32), repeat: Repeat, n: usize) -> Vec<NaiveDate>
+        {
+            let (y, m, d) = start;
+            occurrences(NaiveDate::from_ymd(y, m, d), repeat).take(n).collect()
+        }
+
+        assert_eq!(
+            test((2017, 05, 27), Repeat::Days(7), 3),
+            vec![NaiveDate::from_ymd(2017, 05, 27), NaiveDate::from_ymd(2017, 06, 03), NaiveDate::from_ymd(2017, 06, 10)]);
+
+        assert_eq!(
+            test((2017, 12, 15), Repeat::Months(1), 2),
+            vec![NaiveDate::from_ymd(2017, 12, 15), NaiveDate::from_ymd(2018, 01, 15)]);
+
+        assert_eq!(test((2017, 05, 27), Repeat::Never, 5), vec![NaiveDate::from_ymd(2017, 05, 27)]);
+        assert_eq!(test((2017, 05, 27), Repeat::Days(0), 5), vec![NaiveDate::from_ymd(2017, 05, 27)]);
+
+        let start = NaiveDate::from_ymd(2017, 05, 27);
+        assert!(occurs_on(start, Repeat::Days(7), NaiveDate::from_ymd(2017, 07, 01)));
+        assert!(!occurs_on(start, Repeat::Days(7), NaiveDate::from_ymd(2017, 07, 02)));
+        assert!(!occurs_on(start, Repeat::Days(7), NaiveDate::from_ymd(2017, 05, 20)));
+        assert!(occurs_on(start, Repeat::Never, start));
+        assert!(!occurs_on(start, Repeat::Never, NaiveDate::from_ymd(2017, 06, 03)));
+    }
+
+
+    #[test]
+    fn test_periods_missed()
+    {
+        fn test(due: (i32, u32, u32), completed: (i32, u32, u32), repeat: Repeat) -> u32
+        {
+            let (dy, dm, dd) = due;
+            let (cy, cm, cd) = completed;
+            periods_missed(NaiveDate::from_ymd(dy, dm, dd), NaiveDate::from_ymd(cy, cm, cd), repeat)
+        }
+
+        assert_eq!(test((2017, 05, 01), (2017, 04, 28), Repeat::Days(1)), 0);
+        assert_eq!(test((2017, 05, 01), (2017, 05, 01), Repeat::Days(1)), 0);
+        assert_eq!(test((2017, 05, 01), (2017, 05, 02), Repeat::Days(1)), 1);
+        assert_eq!(test((2017, 05, 01), (2017, 05, 15), Repeat::Days(1)), 14);
+        assert_eq!(test((2017, 05, 01), (2017, 05, 14), Repeat::Weeks(1)), 1);
+        assert_eq!(test((2017, 01, 31), (2017, 05, 01), Repeat::Months(1)), 3);
+        assert_eq!(test((2017, 05, 01), (2018, 05, 01), Repeat::Never), 0);
+    }
+
+
+    fn task(name: &str, date_due: NaiveDate, repeat: Repeat, at_least: bool) -> Task
+    {
+        Task::new(name, repeat).with_due(date_due).with_at_least(at_least)
+    }
+
+
+    #[test]
+    fn test_schedule_add_remove_find()
+    {
+        let date = NaiveDate::from_ymd(2017, 05, 01);
+        let mut schedule = Schedule::default();
+
+        schedule.add_task(task("dishes", date, Repeat::Days(1), false)).unwrap();
+        schedule.add_task(task("laundry", date, Repeat::Weeks(1), false)).unwrap();
+
+        assert_eq!(schedule.add_task(task("dishes", date, Repeat::Days(2), false)), Err(Error::DuplicateName("dishes".into())));
+        assert_eq!(schedule.add_task(task("  ", date, Repeat::Days(2), false)), Err(Error::EmptyName));
+        assert_eq!(schedule.tasks.len(), 2);
+
+        let id = schedule.find("dishes").unwrap().id;
+        assert!(schedule.find("Dishes").is_none());
+
+        assert_eq!(schedule.remove_task(id).map(|task| task.name), Some("dishes".into()));
+        assert!(schedule.remove_task(id).is_none());
+        assert!(schedule.find("dishes").is_none());
+        assert_eq!(schedule.tasks[0].name, "laundry");
+    }
+
+
+    #[test]
+    fn test_task_complete()
+    {
+        let date = |d| NaiveDate::from_ymd(2017, 05, d);
+
+        let mut regular = task("dishes", date(1), Repeat::Days(7), false);
+        assert_eq!(regular.complete(date(3)), Ok(Outcome::Rescheduled(date(8))));
+        assert_eq!(regular.date_completed.as_ref().map(Date::as_naive), Some(date(3)));
+        assert_eq!(regular.history.len(), 1);
+        assert_eq!(regular.history[0].was_due.as_ref().map(Date::as_naive), Some(date(1)));
+        assert_eq!(regular.complete(date(20)), Ok(Outcome::Rescheduled(date(22))));
+
+        let mut at_least = task("haircut", date(1), Repeat::Days(7), true);
+        assert_eq!(at_least.complete(date(3)), Ok(Outcome::Rescheduled(date(10))));
+
+        let mut behind = task("plants", date(1), Repeat::Days(2), false);
+        assert_eq!(behind.catch_up(date(6)), Ok(Outcome::Rescheduled(date(3))));
+        assert_eq!(behind.catch_up(date(6)), Ok(Outcome::Rescheduled(date(5))));
+        assert_eq!(behind.catch_up(date(6)), Ok(Outcome::Rescheduled(date(7))));
+
+        let mut once = task("taxes", date(1), Repeat::Never, false);
+        assert_eq!(once.complete(date(2)), Ok(Outcome::Finished));
+        assert_eq!(once.date_due.as_naive(), date(1));
+
+        let mut broken = task("broken", date(1), Repeat::Days(0), false);
+        assert_eq!(broken.complete(date(2)), Err(Error::UnsupportedRecurrence(Repeat::Days(0))));
+        assert!(broken.history.is_empty());
+    }
+
+
+    #[test]
+    fn test_task_repeat_until()
+    {
+        let date = |d| NaiveDate::from_ymd(2017, 05, d);
+
+        let mut physio = task("physio", date(1), Repeat::Weeks(1), false).with_repeat_until(Some(date(22)));
+        assert_eq!(physio.complete(date(1)), Ok(Outcome::Rescheduled(date(8))));
+        assert_eq!(physio.complete(date(8)), Ok(Outcome::Rescheduled(date(15))));
+        assert_eq!(physio.complete(date(15)), Ok(Outcome::Rescheduled(date(22))));
+        assert_eq!(physio.complete(date(22)), Ok(Outcome::Finished));
+        assert_eq!(physio.history.len(), 4);
+
+        let mut at_least = task("haircut", date(1), Repeat::Days(7), true).with_repeat_until(Some(date(10)));
+        assert_eq!(at_least.complete(date(2)), Ok(Outcome::Rescheduled(date(9))));
+        assert_eq!(at_least.complete(date(4)), Ok(Outcome::Finished));
+    }
+
+
+    #[test]
+    fn test_task_repeat_count()
+    {
+        let date = |d| NaiveDate::from_ymd(2017, 05, d);
+
+        let mut physio = task("physio", date(1), Repeat::Weeks(1), false).with_repeat_count(Some(2));
+        assert_eq!(physio.complete(date(1)), Ok(Outcome::Rescheduled(date(8))));
+        assert_eq!(physio.repeat_count, Some(1));
+        assert_eq!(physio.complete(date(8)), Ok(Outcome::Finished));
+        assert_eq!(physio.repeat_count, Some(0));
+
+        let mut broken = task("broken", date(1), Repeat::Days(0), false).with_repeat_count(Some(2));
+        assert!(broken.complete(date(1)).is_err());
+        assert_eq!(broken.repeat_count, Some(2));
+    }
+
+
+    #[test]
+    fn test_failed_write_leaves_file_intact()
+    {
+        use std::fs;
+
+        struct Unserializable;
+
+        impl serde::Serialize for Unserializable
+        {
+            fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error>
+            {
+                Err(serde::ser::Error::custom("simulated failure"))
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("doq-atomic-write-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("schedule.yaml");
+
+        Schedule::default().save(&path).unwrap();
+        let before = fs::read(&path).unwrap();
+
+        assert!(write_file(&path, &Unserializable).is_err());
+        assert_eq!(fs::read(&path).unwrap(), before);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+
+    #[test]
+    fn test_schedule_round_trips_through_each_format()
+    {
+        use std::fs;
+
+        let yaml = "tasks:\n  - name: dishes\n    date_completed: \"2017-05-01\"\n    date_due: \"2017-05-02\"\n    repeat: 1d\n    at_least: false\n    history:\n      - date: \"2017-05-01\"\n        was_due: \"2017-05-01\"\n    tags: [kitchen]\n    note: Use the good sponge\n  - name: taxes\n    date_completed: ~\n    date_due: \"2018-01-31\"\n    repeat: 1y\n    at_least: true\n";
+        let schedule = Schedule::parse(yaml, FileFormat::Yaml).unwrap();
+        let expected = serde_yaml::to_string(&schedule).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("doq-formats-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for (name, format) in &[("schedule.yml", FileFormat::Yaml), ("schedule.json", FileFormat::Json), ("schedule.toml", FileFormat::Toml), ("schedule", FileFormat::Yaml)]
+        {
+            let path = dir.join(name);
+            assert_eq!(FileFormat::from_path(&path), *format);
+
+            schedule.save(&path).unwrap();
+            let loaded = Schedule::load(&path).unwrap();
+
+            assert_eq!(serde_yaml::to_string(&loaded).unwrap(), expected, "{}", name);
+        }
+
+        assert!(matches!(Schedule::load(&dir.join("missing.yaml")), Err(Error::Io(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+
+    #[test]
+    fn test_task_mark_done()
+    {
+        let date = |d| NaiveDate::from_ymd(2017, 05, d);
+
+        let mut recurring = Task::new("dishes", Repeat::Days(2)).with_due(date(1)).with_tags(vec!["kitchen".into()]);
+        assert!(!recurring.mark_done(date(1)));
+        assert_eq!(recurring.date_due.as_naive(), date(3));
+        assert_eq!(recurring.tags, vec!["kitchen".to_owned()]);
+        assert!(!recurring.mark_done(date(4)));
+        assert_eq!(recurring.date_due.as_naive(), date(5));
+        assert_eq!(recurring.history.len(), 2);
+
+        let mut once = Task::new("taxes", Repeat::Never).with_due(date(1));
+        assert!(once.mark_done(date(1)));
+        assert_eq!(once.date_completed.as_ref().map(Date::as_naive), Some(date(1)));
+    }
+
+
+    #[test]
+    fn test_schedule_index()
+    {
+        let date = NaiveDate::from_ymd(2017, 05, 01);
+        let mut schedule = Schedule::default();
+        schedule.tasks.push(task("dishes", date, Repeat::Days(1), false));
+        schedule.tasks.push(task("laundry", date, Repeat::Days(7), false));
+        schedule.tasks.push(task("dishes", date, Repeat::Days(2), false));
+
+        let index = schedule.index();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index["dishes"], 0);
+        assert_eq!(index["laundry"], 1);
+        assert!(!index.contains_key("Dishes"));
+    }
+
+
+    #[test]
+    fn test_task_changes()
+    {
+        let date = NaiveDate::from_ymd(2017, 05, 01);
+        let before = task("dishes", date, Repeat::Days(1), false);
+        assert_eq!(task_changes(&before, &before), vec![]);
+
+        let mut after = task("dishes", date, Repeat::Days(1), false);
+        after.complete(date).unwrap();
+        after.repeat = Repeat::Days(2);
+
+        let fields: Vec<_> = task_changes(&before, &after).into_iter().map(|change| (change.field, change.before, change.after)).collect();
+        assert_eq!(fields, vec![
+            ("date_due", "2017-05-01".to_owned(), "2017-05-02".to_owned()),
+            ("repeat", "1d".to_owned(), "2d".to_owned()),
+            ("date_completed", "never".to_owned(), "2017-05-01".to_owned()),
+            ("completions", "0".to_owned(), "1".to_owned())
+        ]);
+    }
+
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encryption_round_trip()
+    {
+        let encrypted = encryption::encrypt("tasks: []", "hunter2").unwrap();
+        assert!(encryption::is_encrypted(&encrypted));
+        assert!(!encryption::is_encrypted(b"tasks: []"));
+        assert_ne!(encrypted, encryption::encrypt("tasks: []", "hunter2").unwrap());
+
+        assert_eq!(encryption::decrypt(&encrypted, "hunter2"), Ok("tasks: []".to_owned()));
+        assert!(matches!(encryption::decrypt(&encrypted, "hunter3"), Err(Error::Encryption(_))));
+        assert!(matches!(encryption::decrypt(&encrypted[..20], "hunter2"), Err(Error::Encryption(_))));
+    }
+
+
+    #[test]
+    fn test_fold_name()
+    {
+        assert_eq!(fold_name("DISHES"), "dishes");
+        assert_eq!(fold_name("Café Crème"), "cafe creme");
+        assert_eq!(fold_name("Señor Ødegård"), "senor odegard");
+    }
+
+
+    #[test]
+    fn test_upversion_failures_are_named()
+    {
+        let yaml = "tasks:\n  - name: dishes\n    frequency_days: 0\n    last_completed: \"2017-05-01\"\n  - name: hoover\n    frequency_days: 7\n    last_completed: \"2017-05-01\"\narchived:\n  - name: taxes\n    frequency_days: 0\n    last_completed: \"2017-05-01\"\n";
+
+        let error = Schedule::parse(yaml, FileFormat::Yaml).unwrap_err();
+        assert_eq!(error, Error::Upversion(vec!["dishes".into(), "taxes".into()]));
+        assert!(error.to_string().contains("'dishes', 'taxes'"));
+
+        let yaml = "tasks:\n  - name: hoover\n    frequency_days: 7\n    last_completed: \"2017-05-01\"\n";
+        let schedule = Schedule::parse(yaml, FileFormat::Yaml).unwrap();
+        assert_eq!(schedule.tasks[0].date_due.as_naive(), NaiveDate::from_ymd(2017, 05, 08));
+    }
+
+
+    #[test]
+    fn test_parse_errors_have_locations()
+    {
+        let error = Schedule::parse("tasks:\n  - name: dishes\n   bad: [\n", FileFormat::Yaml).unwrap_err();
+        match error
+        {
+            Error::Parse(ref reason, Some((3, _))) => assert!(!reason.contains(" at line ")),
+            ref e => panic!("Unexpected error: {:?}", e)
+        }
+        assert!(error.to_string().starts_with("Failed to parse file at line 3, column "));
+
+        let error = Schedule::parse("{\n  \"tasks\": [}", FileFormat::Json).unwrap_err();
+        assert!(matches!(error, Error::Parse(_, Some((2, 13)))), "{:?}", error);
+
+        let error = Schedule::parse("tasks = 3", FileFormat::Toml).unwrap_err();
+        assert!(matches!(error, Error::Parse(_, None)), "{:?}", error);
+    }
 }
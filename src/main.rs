@@ -1,8 +1,18 @@
 extern crate ansi_term;
+extern crate chrono;
 extern crate clap;
 extern crate close_enough;
+extern crate dirs;
+#[cfg(feature = "notify")]
+extern crate notify_rust;
+#[cfg(feature = "encryption")]
+extern crate rpassword;
 extern crate serde;
-extern crate serde_yaml;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate term_size;
+extern crate textwrap;
 
 
 extern crate doq;
@@ -10,8 +20,176 @@ extern crate doq;
 
 use std::path::{ Path, PathBuf };
 use ansi_term::Color;
-use serde::{ Serialize, Deserialize };
+use serde::Deserialize;
 use doq::data::*;
+use doq::FileFormat;
+
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct AppConfig
+{
+    /// Which timezone "today" is in: local, utc, or a fixed offset like +02:00.
+    timezone: Option<String>,
+
+    /// The schedule to use when no --file is given and there's no ./.doq.
+    schedule_file: Option<PathBuf>,
+
+    /// Warn when a task is marked done more than this many days before it's due.
+    /// Doesn't apply to at_least tasks. Defaults to 3.
+    early_completion_days: Option<u32>,
+
+    /// Show tasks due within this many days in yellow rather than green.
+    /// Unset, only tasks due today are yellow.
+    warn_within_days: Option<u32>,
+
+    /// The repeat for `add` to use when no --repeat is given, like 1d or weekly.
+    default_repeat: Option<String>,
+
+    /// Make tasks from `add` at_least by default, unless they never repeat.
+    default_at_least: bool,
+
+    /// How to show dates in the listing, as a strftime format like "%a %d %b".
+    /// Defaults to YYYY-MM-DD.
+    date_format: Option<String>,
+
+    /// Only list tasks due within this many days, unless --all or --due-within
+    /// is given. Overdue tasks are always listed.
+    default_horizon_days: Option<i64>,
+
+    /// The day weeks start on in the calendar, like mon or sun. Defaults to Monday.
+    #[serde(deserialize_with = "deserialize_weekday")]
+    week_start: Weekday,
+
+    /// A shell command to run after `did` completes a task. {name}, {date},
+    /// {due} and {next} are replaced with the task's name, the day it was
+    /// done, the day it was due, and the day it's next due (or never).
+    on_complete: Option<String>,
+
+    /// Commit the schedule file to git each time it's saved, if it's in a repository.
+    git_autocommit: bool,
+
+    /// Encrypt the schedule when saving it, with a passphrase read from
+    /// DOQ_PASSPHRASE or asked for. Needs the encryption feature. Encrypted
+    /// files stay encrypted whether or not this is set.
+    encrypt: bool
+}
+
+impl Default for AppConfig
+{
+    fn default() -> Self
+    {
+        AppConfig
+        {
+            timezone: None,
+            schedule_file: None,
+            early_completion_days: None,
+            warn_within_days: None,
+            default_repeat: None,
+            default_at_least: false,
+            date_format: None,
+            default_horizon_days: None,
+            week_start: Weekday::Mon,
+            on_complete: None,
+            git_autocommit: false,
+            encrypt: false
+        }
+    }
+}
+
+fn deserialize_weekday<'de, D>(deserializer: D) -> Result<Weekday, D::Error>
+where
+    D: serde::Deserializer<'de>
+{
+    let day = String::deserialize(deserializer)?;
+    day.parse().map_err(|_| serde::de::Error::custom(format!("Expected a day of the week like mon or sun, not '{}'", day)))
+}
+
+
+/// A task as listed in a file for `import`.
+#[derive(Debug, Deserialize)]
+struct ImportedTask
+{
+    name: String,
+    repeat: String,
+
+    #[serde(default)]
+    on: Option<String>,
+
+    #[serde(default)]
+    at_least: bool,
+
+    #[serde(default)]
+    tags: Vec<String>
+}
+
+impl ImportedTask
+{
+    fn into_task(self, today: NaiveDate) -> Result<Task, String>
+    {
+        let name = self.name.trim();
+        validate_name(name.to_owned())?;
+
+        let repeat = doq::repeat_from_string(&self.repeat).map_err(|e| format!("'{}': {}", name, e))?;
+
+        if repeat == Repeat::Never && self.at_least
+        {
+            return Err(format!("'{}': Cannot specify at_least and repeat never", name));
+        }
+
+        let date_due = match self.on
+        {
+            Some(ref on) => doq::date_from_string(on, today).map_err(|e| format!("'{}': Invalid date '{}'. {}", name, on, e))?,
+            None => today
+        };
+
+        Ok(Task::new(name, repeat).with_due(date_due).with_at_least(self.at_least).with_tags(self.tags))
+    }
+}
+
+
+const DEFAULT_EARLY_COMPLETION_DAYS: u32 = 3;
+
+const BASH_COMPLETIONS: &str = r#"_doq()
+{
+    local cur=${COMP_WORDS[COMP_CWORD]}
+
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY=($(compgen -W "add edit remove move did archive restore log undo snooze reset-overdue pause resume skip export stats heatmap forecast agenda due next watch digest projects calendar import merge diff migrate completions list" -- "$cur"))
+        return
+    fi
+
+    case "${COMP_WORDS[1]}" in
+        edit|remove|move|did|log|undo|snooze|pause|resume|skip|stats|forecast)
+            local IFS=$'\n'
+            COMPREPLY=($(doq __complete "$cur" 2>/dev/null | while read -r name; do printf '%q\n' "$name"; done))
+            ;;
+    esac
+}
+
+complete -F _doq doq
+"#;
+
+const ZSH_COMPLETIONS: &str = r#"#compdef doq
+
+_doq()
+{
+    if (( CURRENT == 2 )); then
+        compadd add edit remove move did archive restore log undo snooze reset-overdue pause resume skip export stats heatmap forecast agenda due next watch digest projects calendar import merge diff migrate completions list
+        return
+    fi
+
+    case $words[2] in
+        edit|remove|move|did|log|undo|snooze|pause|resume|skip|stats|forecast)
+            local -a names
+            names=("${(@f)$(doq __complete "$words[CURRENT]" 2>/dev/null)}")
+            compadd -U -a names
+            ;;
+    esac
+}
+
+_doq "$@"
+"#;
 
 
 fn fail(message: &str) -> !
@@ -20,6 +198,11 @@ fn fail(message: &str) -> !
     std::process::exit(1);
 }
 
+fn warn(message: &str)
+{
+    eprintln!("doq: warning: {}", message);
+}
+
 
 trait OrFail<T>
 {
@@ -45,55 +228,210 @@ impl<T> OrFail<T> for Option<T>
 
 fn main()
 {
-    use clap::{App, SubCommand, Arg, AppSettings};
+    use clap::{App, SubCommand, Arg, ArgGroup, AppSettings};
 
     // TODO: Change to using `structopt` with Date/Day types like `piggy`
-    // TODO: Add validator for --repeat option
-    // TODO: Add validator for dates
     // TODO: Fuzzy matching on all commands
     // TODO: Confirmation prompt on all destructive actions
-    // TODO: Add flags to limit what is shown in schedule
-    // TODO: Use data format that preserves all history
     let app = App::new("doq")
         .version(env!("CARGO_PKG_VERSION"))
         .about("Tool for tracking tasks which need done regularly.")
         .settings(&[AppSettings::VersionlessSubcommands])
         .arg(
             Arg::with_name("file")
-                .help("The schedule file to read and write from. Defaults to the file specified in ~/.doq")
+                .help("The schedule file to read and write from, or - for stdin and stdout. Defaults to ./.doq if it exists, then the schedule_file in the config, then ~/.doq")
                 .long("file")
                 .short("f")
                 .takes_value(true)
             )
+        .arg(
+            Arg::with_name("format")
+                .help("How to print the schedule")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["table", "json"])
+                .default_value("table")
+            )
+        .arg(
+            Arg::with_name("color")
+                .help("When to color the schedule. By default, colors are used unless NO_COLOR is set or stdout isn't a terminal")
+                .long("color")
+                .takes_value(true)
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto")
+            )
+        .arg(
+            Arg::with_name("no_color")
+                .help("Don't color the schedule. Same as --color never")
+                .long("no-color")
+            )
+        .arg(
+            Arg::with_name("tag")
+                .help("Only show tasks with this tag")
+                .long("tag")
+                .takes_value(true)
+            )
+        .arg(
+            Arg::with_name("project")
+                .help("Only show tasks in this project")
+                .long("project")
+                .takes_value(true)
+            )
+        .arg(
+            Arg::with_name("quiet")
+                .help("Don't print the schedule after running a subcommand")
+                .long("quiet")
+                .short("q")
+                .global(true)
+            )
+        .arg(
+            Arg::with_name("check")
+                .help("Exit with status 2 if any task is overdue and not waiting on another, 1 on error, and 0 otherwise. Combine with --quiet to skip printing the schedule")
+                .long("check")
+            )
+        .arg(
+            Arg::with_name("notes")
+                .help("Show the notes attached to each task")
+                .long("notes")
+            )
+        .arg(
+            Arg::with_name("porcelain")
+                .help("Print one task per line as tab-separated fields, for scripts: name, repeat, due, completed (or -), days until due, and status (overdue, due_today, upcoming, or paused)")
+                .long("porcelain")
+            )
+        .arg(
+            Arg::with_name("symbols")
+                .help("Mark each task with a symbol for its status, for reading without color")
+                .long("symbols")
+            )
+        .arg(
+            Arg::with_name("all")
+                .help("Include paused tasks in the schedule")
+                .long("all")
+                .global(true)
+            )
+        .arg(
+            Arg::with_name("allow_duplicates")
+                .help("Allow more than one task with the same name. Use --id to tell them apart")
+                .long("allow-duplicates")
+                .global(true)
+            )
+        .arg(
+            Arg::with_name("dry_run")
+                .help("Show the schedule as it would be after a change, without saving it")
+                .long("dry-run")
+                .global(true)
+            )
+        .arg(
+            Arg::with_name("sort")
+                .help("How to order the schedule. Ties are ordered by due date")
+                .long("sort")
+                .takes_value(true)
+                .possible_values(&["due", "priority"])
+                .default_value("due")
+            )
+        .arg(
+            Arg::with_name("group_by")
+                .help("List tasks in sections. Tasks with several tags are listed under each")
+                .long("group-by")
+                .takes_value(true)
+                .possible_values(&["tag"])
+            )
 
         .subcommand(
             SubCommand::with_name("add")
                 .about("Add a task to track")
                 .arg(
                     Arg::with_name("name")
-                        .help("The name of the task to track")
+                        .help("The name of the task to track. Asked for if omitted at a terminal")
                         .takes_value(true)
-                        .required(true)
+                        .validator(validate_name)
+                    )
+                .arg(
+                    Arg::with_name("interactive")
+                        .help("Ask for the name, repeat, and due date if they aren't given")
+                        .long("interactive")
+                        .short("i")
                     )
                 .arg(
                     Arg::with_name("on")
-                        .help("The date this task is due to be completed on")
+                        .help("The date this task is due to be completed on (YYYY-MM-DD, today, tomorrow, yesterday, or an offset like +5d)")
                         .takes_value(true)
                         .long("on")
+                        .allow_hyphen_values(true)
+                        .validator(validate_date)
+                    )
+                .arg(
+                    Arg::with_name("done_on")
+                        .help("When this task was last completed, to work out when it's next due from")
+                        .takes_value(true)
+                        .long("done-on")
+                        .allow_hyphen_values(true)
+                        .validator(validate_date)
                     )
                 .arg(
                     Arg::with_name("repeat")
-                        .help("How frequently this task should repeat")
+                        .help("How frequently this task should repeat (e.g. 3d, 5b for business days, 2w, 1m, 1y, 1y6m, m15 for the 15th of each month, 2tue for the 2nd Tuesday, daily, weekly, biweekly, monthly, yearly, or never). Defaults to default_repeat in the config")
                         .short("r")
                         .long("repeat")
                         .takes_value(true)
-                        .required(true)
+                        .validator(validate_repeat)
                     )
                 .arg(
                     Arg::with_name("at_least")
                         .help("Specify that the repeat period is relative to completion date rather than due date")
                         .long("at-least")
                     )
+                .arg(
+                    Arg::with_name("times")
+                        .help("How many times the task needs doing before it's finished")
+                        .long("times")
+                        .takes_value(true)
+                        .validator(validate_times)
+                    )
+                .arg(
+                    Arg::with_name("until")
+                        .help("Stop repeating after this date. The task is finished once it would next be due later")
+                        .long("until")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .validator(validate_date)
+                    )
+                .arg(
+                    Arg::with_name("priority")
+                        .help("How important this task is, from 0 (default) to 255")
+                        .long("priority")
+                        .short("p")
+                        .takes_value(true)
+                        .validator(validate_priority)
+                    )
+                .arg(
+                    Arg::with_name("note")
+                        .help("A note to attach to this task")
+                        .long("note")
+                        .takes_value(true)
+                    )
+                .arg(
+                    Arg::with_name("project")
+                        .help("The project this task belongs to")
+                        .long("project")
+                        .takes_value(true)
+                    )
+                .arg(
+                    Arg::with_name("depends_on")
+                        .help("A task that needs doing before this one each time. Fuzzily matched.")
+                        .long("depends-on")
+                        .takes_value(true)
+                    )
+                .arg(
+                    Arg::with_name("tag")
+                        .help("A tag to group this task under. Can be given multiple times")
+                        .long("tag")
+                        .short("t")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                    )
             )
 
         .subcommand(
@@ -103,19 +441,23 @@ fn main()
                     Arg::with_name("name")
                         .help("The name of the task to modify. Fuzzily matched.")
                         .takes_value(true)
-                        .required(true)
+                        .required_unless("id")
                     )
+                .arg(task_id_arg())
                 .arg(
                     Arg::with_name("rename")
                         .help("New name for the task")
                         .takes_value(true)
                         .long("rename")
+                        .validator(validate_name)
                     )
                 .arg(
                     Arg::with_name("on")
-                        .help("New due-date of the task")
+                        .help("New due-date of the task (YYYY-MM-DD, today, tomorrow, yesterday, or an offset like +5d)")
                         .takes_value(true)
                         .long("on")
+                        .allow_hyphen_values(true)
+                        .validator(validate_date)
                     )
                 .arg(
                     Arg::with_name("repeat")
@@ -123,6 +465,7 @@ fn main()
                         .short("r")
                         .long("repeat")
                         .takes_value(true)
+                        .validator(validate_repeat)
                     )
                 .arg(
                     Arg::with_name("at_least")
@@ -131,6 +474,54 @@ fn main()
                         .takes_value(true)
                         .possible_values(&["true", "false"])
                     )
+                .arg(
+                    Arg::with_name("until")
+                        .help("New date to stop repeating after, or never to keep repeating")
+                        .long("until")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .validator(validate_until)
+                    )
+                .arg(
+                    Arg::with_name("priority")
+                        .help("New importance of the task, from 0 to 255")
+                        .long("priority")
+                        .short("p")
+                        .takes_value(true)
+                        .validator(validate_priority)
+                    )
+                .arg(
+                    Arg::with_name("note")
+                        .help("New note for the task. Pass \"\" to clear it")
+                        .long("note")
+                        .takes_value(true)
+                    )
+                .arg(
+                    Arg::with_name("project")
+                        .help("Move the task to this project. Pass \"\" to clear it")
+                        .long("project")
+                        .takes_value(true)
+                    )
+                .arg(
+                    Arg::with_name("depends_on")
+                        .help("A task that needs doing before this one each time. Fuzzily matched. Pass \"\" to clear it")
+                        .long("depends-on")
+                        .takes_value(true)
+                    )
+                .arg(
+                    Arg::with_name("tag")
+                        .help("Replace the task's tags. Can be given multiple times")
+                        .long("tag")
+                        .short("t")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                    )
+                .arg(
+                    Arg::with_name("clear_completed")
+                        .help("Forget when the task was last completed. Its history is kept")
+                        .long("clear-completed")
+                    )
             )
 
         .subcommand(
@@ -138,340 +529,3409 @@ fn main()
                 .about("Stop tracking a task")
                 .arg(
                     Arg::with_name("name")
-                        .help("The name of the task to remove")
+                        .help("The name of the task to remove. Fuzzily matched.")
+                        .takes_value(true)
+                        .required_unless("id")
+                    )
+                .arg(task_id_arg())
+                .arg(
+                    Arg::with_name("yes")
+                        .help("Bypass confirmation prompt")
+                        .short("y")
+                        .long("yes")
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("move")
+                .about("Move a task, with its history, to another schedule file")
+                .arg(
+                    Arg::with_name("name")
+                        .help("The name of the task to move. Fuzzily matched.")
+                        .takes_value(true)
+                        .required_unless("id")
+                    )
+                .arg(task_id_arg())
+                .arg(
+                    Arg::with_name("to")
+                        .help("The schedule file to move the task to. Created if it doesn't exist")
                         .takes_value(true)
+                        .long("to")
                         .required(true)
                     )
             )
 
         .subcommand(
             SubCommand::with_name("did")
-                .about("Mark a task as done")
+                .about("Mark tasks as done")
                 .arg(
                     Arg::with_name("task")
-                        .help("The name of the task to mark done. Fuzzily matched.")
+                        .help("The names of the tasks to mark done. Fuzzily matched.")
                         .takes_value(true)
-                        .required(true)
+                        .multiple(true)
+                        .required_unless("id")
+                        .conflicts_with("id")
                     )
+                .arg(task_id_arg())
                 .arg(
                     Arg::with_name("on")
-                        .help("Specify the date of completion")
+                        .help("Specify the date of completion (YYYY-MM-DD, today, tomorrow, yesterday, or an offset like +5d)")
                         .long("on")
+                        .allow_hyphen_values(true)
+                        .validator(validate_date)
                         .takes_value(true)
                     )
                 .arg(
                     Arg::with_name("yes")
                         .help("Bypass confirmation prompt")
                         .short("y")
+                        .long("yes")
                     )
-            );
-
-    let matches = app.get_matches();
-
-    let dotfile = &{
-        match matches.value_of("file")
-        {
-            Some(file) => PathBuf::from(file),
-            None => {
-                let here: &Path = "./.doq".as_ref();
+                .arg(
+                    Arg::with_name("force")
+                        .help("Allow a completion date in the future without asking")
+                        .long("force")
+                    )
+                .arg(
+                    Arg::with_name("catch_up")
+                        .help("If occurrences were missed, only advance to the next missed one rather than past the completion date")
+                        .long("catch-up")
+                    )
+            )
 
-                if here.exists()
-                {
-                    here.to_owned()
-                }
-                else
-                {
-                    use std::env;
-                    let mut home = env::home_dir().expect("Failed to find home directory");
-                    home.push(".doq");
-                    home
-                }
-            }
-        }
-    };
+        .subcommand(
+            SubCommand::with_name("archive")
+                .about("Show completed one-off tasks")
+            )
 
-    ensure_file_exists(dotfile, &Schedule::default());
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Move an archived task back into the schedule")
+                .arg(
+                    Arg::with_name("task")
+                        .help("The name of the archived task to restore. Fuzzily matched.")
+                        .takes_value(true)
+                        .required(true)
+                    )
+                .arg(
+                    Arg::with_name("on")
+                        .help("When the restored task is due (YYYY-MM-DD, today, tomorrow, yesterday, or an offset like +5d). Defaults to today")
+                        .long("on")
+                        .allow_hyphen_values(true)
+                        .validator(validate_date)
+                        .takes_value(true)
+                    )
+            )
 
-    let mut schedule = {
-        let schedule: VersionedSchedule = read_file(dotfile);
-        let tasks = schedule.tasks.into_iter().map(|task| task.upversioned().or_fail("Failed to upversion old tasks in schedule. You may have to manually recreate it.")).collect();
+        .subcommand(
+            SubCommand::with_name("log")
+                .about("Show the recorded completions of a task")
+                .arg(
+                    Arg::with_name("task")
+                        .help("The name of the task to show. Fuzzily matched.")
+                        .takes_value(true)
+                        .required_unless("id")
+                    )
+                .arg(task_id_arg())
+            )
 
-        Schedule { tasks }
-    };
+        .subcommand(
+            SubCommand::with_name("undo")
+                .about("Revert the most recent completion of a task")
+                .arg(
+                    Arg::with_name("task")
+                        .help("The name of the task to revert. Fuzzily matched.")
+                        .takes_value(true)
+                        .required_unless("id")
+                    )
+                .arg(task_id_arg())
+            )
 
-    match matches.subcommand()
-    {
-        ("add", Some(matches)) =>
-        {
-            let name = matches.value_of("name").unwrap();
-            let repeat = {
-                let value = matches.value_of("repeat").unwrap();
+        .subcommand(
+            SubCommand::with_name("snooze")
+                .about("Push a task's due date back without marking it done")
+                .arg(
+                    Arg::with_name("task")
+                        .help("The name of the task to snooze. Fuzzily matched.")
+                        .takes_value(true)
+                        .required_unless("id")
+                    )
+                .arg(task_id_arg())
+                .arg(
+                    Arg::with_name("duration")
+                        .help("How long to snooze the task for (e.g. 2d, 1w, 1m)")
+                        .takes_value(true)
+                        .validator(validate_offset)
+                        .required_unless("id")
+                    )
+            )
 
-                if value == "never"
-                {
-                    Repeat::Never
-                }
-                else
-                {
-                    let (count, unit) = value.split_at(value.len() - 1);
-                    let count: u32 = count.parse().or_fail("Expected a number");
+        .subcommand(
+            SubCommand::with_name("reset-overdue")
+                .about("Move every overdue task's due date to today, without marking any done")
+                .arg(
+                    Arg::with_name("to")
+                        .help("The date to move overdue tasks to (YYYY-MM-DD, today, tomorrow, yesterday, or an offset like +2d). Defaults to today")
+                        .long("to")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .validator(validate_date)
+                    )
+                .arg(
+                    Arg::with_name("yes")
+                        .help("Bypass confirmation prompt")
+                        .short("y")
+                        .long("yes")
+                    )
+            )
 
-                    match unit
-                    {
-                        "d" => Repeat::Days(count),
-                        "m" => Repeat::Months(count),
-                        "y" => Repeat::Years(count),
-                        _ => fail("Expected a suffix (d, m, y) for days, months, or years")
-                    }
-                }
-            };
+        .subcommand(
+            SubCommand::with_name("pause")
+                .about("Stop tracking a task without deleting it. Paused tasks are hidden unless --all is given")
+                .arg(
+                    Arg::with_name("task")
+                        .help("The name of the task to pause. Fuzzily matched.")
+                        .takes_value(true)
+                        .required_unless("id")
+                    )
+                .arg(task_id_arg())
+            )
 
-            let at_least = matches.is_present("at_least");
+        .subcommand(
+            SubCommand::with_name("resume")
+                .about("Start tracking a paused task again")
+                .arg(
+                    Arg::with_name("task")
+                        .help("The name of the task to resume. Fuzzily matched.")
+                        .takes_value(true)
+                        .required_unless("id")
+                    )
+                .arg(task_id_arg())
+            )
 
-            if repeat == Repeat::Never && at_least
-            {
-                fail("Cannot specify --at-least and --repeat never");
-            }
+        .subcommand(
+            SubCommand::with_name("skip")
+                .about("Skip the current occurrence of a recurring task without marking it done")
+                .arg(
+                    Arg::with_name("task")
+                        .help("The name of the task to skip. Fuzzily matched.")
+                        .takes_value(true)
+                        .required_unless("id")
+                    )
+                .arg(task_id_arg())
+            )
 
-            if schedule.tasks.iter().find(|t| t.name == name).is_some()
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export the schedule in another format")
+                .arg(
+                    Arg::with_name("csv")
+                        .help("Export as comma-separated values")
+                        .long("csv")
+                    )
+                .arg(
+                    Arg::with_name("ics")
+                        .help("Export due dates as an iCalendar file")
+                        .long("ics")
+                    )
+                .arg(
+                    Arg::with_name("markdown")
+                        .help("Export the listing as a Markdown table. Takes the same filters as list")
+                        .long("markdown")
+                    )
+                .arg(
+                    Arg::with_name("html")
+                        .help("Export the listing as a standalone HTML page. Takes the same filters as list")
+                        .long("html")
+                    )
+                .group(
+                    ArgGroup::with_name("export_format")
+                        .args(&["csv", "ics", "markdown", "html"])
+                        .required(true)
+                    )
+                .args(&filter_args())
+                .arg(
+                    Arg::with_name("output")
+                        .help("The file to write to. Defaults to stdout")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Show how consistently tasks have been completed")
+                .arg(
+                    Arg::with_name("intervals")
+                        .help("Also show the mean and median days between completions next to each task's repeat")
+                        .long("intervals")
+                    )
+                .arg(
+                    Arg::with_name("task")
+                        .help("The name of a single task to show. Fuzzily matched.")
+                        .takes_value(true)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("heatmap")
+                .about("Show a grid of completions per day over the last year")
+                .arg(
+                    Arg::with_name("task")
+                        .help("The name of a single task to show. Fuzzily matched. Defaults to all tasks")
+                        .takes_value(true)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("forecast")
+                .about("Show the upcoming due dates of a task, assuming it's done on time")
+                .arg(
+                    Arg::with_name("task")
+                        .help("The name of the task to forecast. Fuzzily matched.")
+                        .takes_value(true)
+                        .required_unless("id")
+                    )
+                .arg(task_id_arg())
+                .arg(
+                    Arg::with_name("count")
+                        .help("How many due dates to show")
+                        .long("count")
+                        .short("n")
+                        .takes_value(true)
+                        .default_value("5")
+                        .validator(validate_days)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("agenda")
+                .about("Show tasks due over the coming days, grouped by day")
+                .arg(
+                    Arg::with_name("days")
+                        .help("How many days ahead to show")
+                        .long("days")
+                        .takes_value(true)
+                        .default_value("7")
+                        .validator(validate_days)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("due")
+                .about("Show the tasks due on a date, assuming each is done on time until then")
+                .arg(
+                    Arg::with_name("date")
+                        .help("The date to check (YYYY-MM-DD, today, tomorrow, yesterday, or an offset like +7d)")
+                        .required(true)
+                        .allow_hyphen_values(true)
+                        .validator(validate_date)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("next")
+                .about("Show the single most urgent task on one line")
+                .arg(
+                    Arg::with_name("days")
+                        .help("Only show a task due within this many days")
+                        .long("days")
+                        .takes_value(true)
+                        .default_value("7")
+                        .validator(validate_days)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("digest")
+                .about("Print a short plain-text summary of overdue tasks and tasks due today. Prints nothing if there are none")
+            )
+
+        .subcommand(
+            SubCommand::with_name("projects")
+                .about("List projects, with how many of their tasks are overdue")
+            )
+
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Keep the schedule on screen, redrawing it when the file changes or the day rolls over. Ctrl-C to stop")
+                .arg(
+                    Arg::with_name("interval")
+                        .help("How often to check for changes, in seconds")
+                        .long("interval")
+                        .takes_value(true)
+                        .default_value("60")
+                        .validator(validate_seconds)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("calendar")
+                .about("Show a month calendar marking the days tasks are due")
+                .arg(
+                    Arg::with_name("month")
+                        .help("The month to show, as YYYY-MM. Defaults to the current month")
+                        .takes_value(true)
+                        .validator(validate_month)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Add every task listed in a YAML or JSON file of {name, repeat, on, at_least, tags} entries")
+                .arg(
+                    Arg::with_name("file")
+                        .help("The file of tasks to import")
+                        .takes_value(true)
+                        .required(true)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Show how another schedule differs from this one")
+                .arg(
+                    Arg::with_name("other")
+                        .help("The schedule file to compare against")
+                        .takes_value(true)
+                        .required(true)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Add the tasks from another schedule. Where both have a task, the one completed most recently is kept")
+                .arg(
+                    Arg::with_name("other")
+                        .help("The schedule file to merge in")
+                        .takes_value(true)
+                        .required(true)
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Rewrite the schedule in the current format, listing any tasks upgraded from older versions")
+            )
+
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Print a shell completion script, which also completes task names")
+                .arg(
+                    Arg::with_name("shell")
+                        .help("The shell to generate completions for")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["bash", "zsh"])
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("__complete")
+                .about("Print the names of tasks matching a partial name, for shell completion")
+                .setting(AppSettings::Hidden)
+                .arg(
+                    Arg::with_name("prefix")
+                        .help("The partial task name")
+                        .takes_value(true)
+                        .default_value("")
+                    )
+            )
+
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("Show the schedule. This is the default when no subcommand is given")
+                .args(&filter_args())
+                .arg(
+                    Arg::with_name("intervals")
+                        .help("Show the mean and median days between completions next to each task's repeat")
+                        .long("intervals")
+                    )
+            );
+
+    #[cfg(feature = "notify")]
+    let app = app.subcommand(
+        SubCommand::with_name("notify")
+            .about("Show a desktop notification for tasks that are overdue or due today. Prints the digest instead if notifications can't be shown")
+            .arg(
+                Arg::with_name("individual")
+                    .help("Show a notification for each task, instead of one for them all")
+                    .long("individual")
+                )
+        );
+
+    let matches = app.get_matches();
+
+    if let ("completions", Some(sub_matches)) = matches.subcommand()
+    {
+        match sub_matches.value_of("shell").unwrap()
+        {
+            "zsh" => print!("{}", ZSH_COMPLETIONS),
+            _ => print!("{}", BASH_COMPLETIONS)
+        }
+        return;
+    }
+
+    let home = dirs::home_dir().or_fail("Failed to find home directory");
+
+    let config: AppConfig = {
+        let xdg_paths = dirs::config_dir().map(|dir| vec![dir.join("doq").join("config.yaml"), dir.join("doq").join("config.toml")]);
+
+        match xdg_paths.unwrap_or_default().into_iter().chain(Some(home.join(".doq_config"))).find(|path| path.exists())
+        {
+            Some(path) => read_config(&path),
+            None => AppConfig::default()
+        }
+    };
+
+    let default_repeat = config.default_repeat.as_ref().map(|repeat|
+    {
+        doq::repeat_from_string(repeat).unwrap_or_else(|e| fail(&format!("Invalid default_repeat '{}' in config. {}", repeat, e)))
+    });
+
+    let date_format = config.date_format.as_deref().unwrap_or("%Y-%m-%d");
+    if chrono::format::StrftimeItems::new(date_format).any(|item| item == chrono::format::Item::Error)
+    {
+        fail(&format!("Invalid date_format '{}' in config", date_format));
+    }
+
+    let dotfile = &{
+        let here: &Path = "./.doq".as_ref();
+
+        match (matches.value_of("file"), &config.schedule_file)
+        {
+            (Some(file), _) => PathBuf::from(file),
+            _ if here.exists() => here.to_owned(),
+            (None, Some(file)) => expand_home(file, &home),
+            (None, None) => home.join(".doq")
+        }
+    };
+
+    if let ("__complete", Some(sub_matches)) = matches.subcommand()
+    {
+        complete_task_names(dotfile, sub_matches.value_of("prefix").unwrap());
+        return;
+    }
+
+    let dry_run = matches.is_present("dry_run") || matches.subcommand().1.is_some_and(|m| m.is_present("dry_run"));
+    let commit_message = format!("doq: {}", matches.subcommand_name().unwrap_or("list"));
+    let save = SaveOptions { dry_run, encrypt: config.encrypt, commit_message: Some(commit_message.as_str()).filter(|_| config.git_autocommit) };
+
+    if config.encrypt && !cfg!(feature = "encryption")
+    {
+        fail("encrypt is set in the config, but doq was built without the encryption feature");
+    }
+
+    if let ("migrate", Some(_)) = matches.subcommand()
+    {
+        migrate(dotfile, save);
+        return;
+    }
+
+    let today = doq::today(config.timezone.as_deref()).unwrap_or_else(|e| fail(&e.to_string()));
+
+    let mut schedule = match dry_run && !is_stdio(dotfile) && !dotfile.exists()
+    {
+        true => Schedule::default(),
+        false =>
+        {
+            ensure_file_exists(dotfile, &Schedule::default(), config.encrypt);
+            load_schedule(dotfile)
+        }
+    };
+
+    let all = matches.is_present("all") || matches.subcommand().1.is_some_and(|m| m.is_present("all"));
+    let allow_duplicates = matches.is_present("allow_duplicates") || matches.subcommand().1.is_some_and(|m| m.is_present("allow_duplicates"));
+
+    match schedule.validate()
+    {
+        Err(doq::Error::DuplicateName(_)) if allow_duplicates => (),
+        Err(e @ doq::Error::DuplicateName(_)) => fail(&format!("{} in {}. Rename one of them, or pass --allow-duplicates and refer to them by --id", e, dotfile.display())),
+        Err(e) => fail(&format!("{} in {}", e, dotfile.display())),
+        Ok(()) => ()
+    }
+
+    // Built once so that each name lookup, however many there are, is cheap
+    let name_index = schedule.index();
+
+    match matches.subcommand()
+    {
+        ("add", Some(matches)) =>
+        {
+            let interactive = matches.is_present("interactive") || {
+                use std::io::IsTerminal;
+
+                matches.value_of("name").is_none() && matches.value_of("repeat").is_none() && std::io::stdin().is_terminal()
+            };
+
+            let name = match matches.value_of("name")
+            {
+                Some(name) => name.trim().to_owned(),
+                None if interactive => ask("Task name", |name| validate_name(name.to_owned()).map(|_| name.to_owned())),
+                None => fail("No task name given. Pass one, or use --interactive")
+            };
+            let name = name.as_str();
+
+            let repeat = match (matches.value_of("repeat"), default_repeat)
+            {
+                (Some(repeat), _) => doq::repeat_from_string(repeat).unwrap_or_else(|e| fail(&e.to_string())),
+                (None, Some(default)) if interactive => ask(&format!("Repeat (blank for {})", default), |repeat| match repeat
+                {
+                    "" => Ok(default),
+                    repeat => doq::repeat_from_string(repeat).map_err(|e| e.to_string())
+                }),
+                (None, None) if interactive => ask("Repeat (e.g. 3d, 2w, weekly, or never)", |repeat| doq::repeat_from_string(repeat).map_err(|e| e.to_string())),
+                (None, Some(default)) => default,
+                (None, None) => fail("No --repeat given. Pass one, set default_repeat in the config, or use --interactive")
+            };
+
+            let at_least = matches.is_present("at_least") || (config.default_at_least && repeat != Repeat::Never);
+
+            if repeat == Repeat::Never && at_least
+            {
+                fail("Cannot specify --at-least and --repeat never");
+            }
+
+            let done_on = matches.value_of("done_on").map(|date| parse_date(date, today));
+            let date_due = match (matches.value_of("on"), done_on)
+            {
+                (None, Some(done_on)) => done_on,
+                (None, None) if interactive => ask("Due on (blank for today)", |date| match date
+                {
+                    "" => Ok(today),
+                    date => doq::date_from_string(date, today).map_err(|e| format!("Invalid date '{}'. {}", date, e))
+                }),
+                (on, _) => parse_date_or_today(on, today)
+            };
+
+            if repeat == Repeat::Never && done_on.is_some()
+            {
+                fail("Cannot specify --done-on and --repeat never");
+            }
+
+            if repeat == Repeat::Never && matches.is_present("until")
+            {
+                fail("Cannot specify --until and --repeat never");
+            }
+
+            if repeat == Repeat::Never && matches.is_present("times")
+            {
+                fail("Cannot specify --times and --repeat never");
+            }
+
+            let mut task = Task::new(name, repeat)
+                .with_due(date_due)
+                .with_at_least(at_least)
+                .with_repeat_until(matches.value_of("until").map(|date| parse_date(date, today)))
+                .with_repeat_count(matches.value_of("times").map(|times| times.parse().unwrap()))
+                .with_tags(matches.values_of("tag").map(|tags| tags.map(Into::into).collect()).unwrap_or_default())
+                .with_project(matches.value_of("project").filter(|project| !project.is_empty()).map(Into::into))
+                .with_depends_on(matches.value_of("depends_on").map(|name| schedule.tasks[match_task(&schedule.tasks, &name_index, name, false)].name.clone()))
+                .with_priority(matches.value_of("priority").map_or(0, |p| p.parse().unwrap()))
+                .with_note(matches.value_of("note").filter(|note| !note.is_empty()).map(Into::into));
+
+            if let Some(done_on) = done_on
+            {
+                // Like --repeat never, a task that's done with after this completion has nothing left to track
+                if task.complete(done_on).unwrap_or_else(|e| fail(&e.to_string())) == Outcome::Finished
+                {
+                    fail("Cannot specify --done-on for a task with no repeats left after it. Check --times and --until");
+                }
+            }
+
+            match allow_duplicates
+            {
+                true => schedule.tasks.push(task),
+                false => schedule.add_task(task).unwrap_or_else(|e| match e
+                {
+                    doq::Error::DuplicateName(_) => fail("Task already exists"),
+                    e => fail(&e.to_string())
+                })
+            }
+
+            save_schedule(dotfile, &schedule, save);
+        },
+
+        ("edit", Some(matches)) =>
+        {
+            {
+                let index = target_task(&schedule.tasks, &name_index, matches, "name");
+
+                let new_name = matches.value_of("rename").map(str::trim);
+                if let Some(new_name) = new_name
+                {
+                    // The same check as add, so a name add would refuse can't be renamed into
+                    if !allow_duplicates && schedule.find(new_name).is_some_and(|t| t.id != schedule.tasks[index].id)
+                    {
+                        fail("Task already exists");
+                    }
+                }
+
+                let depends_on = matches.value_of("depends_on").map(|name| match name
+                {
+                    "" => None,
+                    name => Some(schedule.tasks[match_task(&schedule.tasks, &name_index, name, false)].name.clone())
+                });
+
+                if let Some(new_name) = new_name
+                {
+                    let old_name = std::mem::replace(&mut schedule.tasks[index].name, new_name.to_owned());
+                    for task in schedule.tasks.iter_mut().filter(|t| t.depends_on.as_ref() == Some(&old_name))
+                    {
+                        task.depends_on = Some(new_name.to_owned());
+                    }
+                }
+
+                let task = &mut schedule.tasks[index];
+
+                if let Some(depends_on) = depends_on
+                {
+                    task.depends_on = depends_on;
+                }
+
+                if let Some(on) = matches.value_of("on")
+                {
+                    task.date_due = parse_date(on, today).into();
+                }
+
+                if let Some(repeat) = matches.value_of("repeat")
+                {
+                    task.repeat = doq::repeat_from_string(repeat).unwrap_or_else(|e| fail(&e.to_string()));
+                }
+
+                if let Some(at_least) = matches.value_of("at_least")
+                {
+                    task.at_least = at_least.parse().unwrap();
+                }
+
+                if let Some(until) = matches.value_of("until")
+                {
+                    task.repeat_until = match until
+                    {
+                        "never" => None,
+                        until => Some(parse_date(until, today).into())
+                    };
+                }
+
+                if let Some(priority) = matches.value_of("priority")
+                {
+                    task.priority = priority.parse().unwrap();
+                }
+
+                if let Some(note) = matches.value_of("note")
+                {
+                    task.note = match note
+                    {
+                        "" => None,
+                        note => Some(note.to_owned())
+                    };
+                }
+
+                if let Some(project) = matches.value_of("project")
+                {
+                    task.project = match project
+                    {
+                        "" => None,
+                        project => Some(project.to_owned())
+                    };
+                }
+
+                if let Some(tags) = matches.values_of("tag")
+                {
+                    task.tags = tags.map(Into::into).collect();
+                }
+
+                if matches.is_present("clear_completed")
+                {
+                    task.date_completed = None;
+                }
+
+                if task.repeat == Repeat::Never && task.at_least
+                {
+                    match matches.is_present("at_least")
+                    {
+                        true => fail("Cannot specify --at-least and --repeat never"),
+                        false => fail(&format!("'{}' repeats --at-least, so can't --repeat never. Pass --at-least false too", task.name))
+                    }
+                }
+            }
+
+            if let Err(e @ doq::Error::DependencyCycle(_)) = schedule.validate()
+            {
+                fail(&e.to_string());
+            }
+
+            save_schedule(dotfile, &schedule, save);
+        }
+
+        ("remove", Some(matches)) =>
+        {
+            let index = target_task(&schedule.tasks, &name_index, matches, "name");
+            let (task_id, task_name) = (schedule.tasks[index].id, schedule.tasks[index].name.clone());
+            let yes = matches.is_present("yes");
+
+            if yes || confirm(&format!("Remove task '{}'?", task_name))
+            {
+                schedule.remove_task(task_id);
+                save_schedule(dotfile, &schedule, save);
+                status(dotfile, &format!("Removed task '{}'", task_name));
+            }
+            else
+            {
+                eprintln!("Cancelling");
+            }
+        }
+
+        ("move", Some(matches)) =>
+        {
+            let to_path = Path::new(matches.value_of("to").unwrap());
+            if is_stdio(to_path)
+            {
+                fail("Cannot move a task to stdout");
+            }
+
+            let mut destination = match to_path.exists()
+            {
+                true => load_schedule(to_path),
+                false => Schedule::default()
+            };
+
+            let index = target_task(&schedule.tasks, &name_index, matches, "name");
+            let task = schedule.tasks.remove(index);
+            let task_name = task.name.clone();
+
+            match allow_duplicates
+            {
+                true => destination.tasks.push(task),
+                false => destination.add_task(task).unwrap_or_else(|e| match e
+                {
+                    doq::Error::DuplicateName(name) => fail(&format!("{} already has a task named '{}'", to_path.display(), name)),
+                    e => fail(&e.to_string())
+                })
+            }
+
+            // Write the destination first, so a failure leaves the task in both files rather than neither
+            save_schedule(to_path, &destination, save);
+            save_schedule(dotfile, &schedule, save);
+            status(dotfile, &format!("Moved task '{}' to {}", task_name, to_path.display()));
+        }
+
+        ("did", Some(matches)) =>
+        {
+            let date = parse_date_or_today(matches.value_of("on"), today);
+            let yes = matches.is_present("yes");
+            let force = matches.is_present("force");
+            let catch_up = matches.is_present("catch_up");
+
+            let mut indices: Vec<usize> = match matches.values_of("task")
+            {
+                Some(names) => names.map(|name| match_task(&schedule.tasks, &name_index, name, yes)).collect(),
+                None => vec![target_task(&schedule.tasks, &name_index, matches, "task")]
+            };
+            let mut seen = std::collections::HashSet::new();
+            indices.retain(|&index| seen.insert(index));
+
+            let names: Vec<_> = indices.iter().map(|&index| format!("'{}'", schedule.tasks[index].name)).collect();
+            let (noun, names) = match names.len()
+            {
+                1 => ("task", names[0].clone()),
+                _ => ("tasks", names.join(", "))
+            };
+
+            let proceed = match date > today
+            {
+                true => yes || force || confirm(&format!("{} is in the future. Mark {} {} as done on {} anyway?", date, noun, names, date)),
+                false => yes || confirm(&format!("Mark {} {} as done on {}?", noun, names, date))
+            };
+
+            if proceed
+            {
+                let mut finished = Vec::new();
+                let mut completed = Vec::new();
+                let mut reported = false;
+
+                for &index in &indices
+                {
+                    if let Some(prerequisite) = schedule.blocked_by(&schedule.tasks[index])
+                    {
+                        warn(&format!("'{}' is waiting on '{}', which hasn't been done since", schedule.tasks[index].name, prerequisite));
+                    }
+
+                    let task = &mut schedule.tasks[index];
+
+                    let date_completed = date;
+                    let previous_date_due = task.date_due.as_naive();
+                    let days_early = doq::days_until_due(previous_date_due, date_completed);
+                    let early_threshold = config.early_completion_days.unwrap_or(DEFAULT_EARLY_COMPLETION_DAYS);
+
+                    if !task.at_least && days_early > early_threshold as i64
+                    {
+                        warn(&format!("'{}' isn't due until {}, {} days after {}", task.name, previous_date_due, days_early, date_completed));
+                    }
+
+                    let missed = doq::periods_missed(previous_date_due, date_completed, task.repeat);
+                    let outcome = match catch_up
+                    {
+                        true => task.catch_up(date_completed),
+                        false => task.complete(date_completed)
+                    };
+
+                    let outcome = outcome.unwrap_or_else(|e| fail(&format!("{} for '{}'", e, task.name)));
+                    completed.push((task.name.clone(), previous_date_due, outcome));
+
+                    match outcome
+                    {
+                        Outcome::Rescheduled(next_due_date) if catch_up && missed > 0 =>
+                        {
+                            let plural = if missed == 1 { "" } else { "s" };
+                            status(dotfile, &format!("'{}' missed {} occurrence{} since {}. Next due {}", task.name, missed, plural, previous_date_due, next_due_date));
+                            reported = true;
+                        },
+                        Outcome::Rescheduled(next_due_date) if indices.len() > 1 =>
+                        {
+                            status(dotfile, &format!("'{}' done. Next due {}", task.name, next_due_date));
+                            reported = true;
+                        },
+                        Outcome::Rescheduled(_) => (),
+                        Outcome::Finished =>
+                        {
+                            if indices.len() > 1
+                            {
+                                status(dotfile, &format!("'{}' done and archived", task.name));
+                                reported = true;
+                            }
+                            finished.push(index);
+                        }
+                    }
+                }
+
+                if reported
+                {
+                    status(dotfile, "");
+                }
+
+                // Remove from the back so the remaining indices stay valid
+                finished.sort_unstable();
+                for index in finished.into_iter().rev()
+                {
+                    let task = schedule.tasks.remove(index);
+                    schedule.archived.push(task);
+                }
+
+                save_schedule(dotfile, &schedule, save);
+
+                if let Some(ref template) = config.on_complete
+                {
+                    if !dry_run
+                    {
+                        for (name, date_due, outcome) in completed
+                        {
+                            run_completion_hook(template, &name, date, date_due, outcome, is_stdio(dotfile));
+                        }
+                    }
+                }
+            }
+            else
+            {
+                eprintln!("Cancelling");
+            }
+        },
+
+        ("archive", Some(_)) =>
+        {
+            print_archive(&schedule.archived, date_format);
+            return;
+        },
+
+        ("import", Some(matches)) =>
+        {
+            let entries: Vec<ImportedTask> = read_file(Path::new(matches.value_of("file").unwrap()), "file");
+
+            // Check every entry before adding any, so a bad one aborts the import
+            let tasks: Vec<Task> = entries.into_iter().enumerate().map(|(i, entry)|
+            {
+                entry.into_task(today).unwrap_or_else(|e| fail(&format!("Entry {} can't be imported: {}", i + 1, e)))
+            }).collect();
+
+            let mut imported = 0;
+            for task in tasks
+            {
+                if !allow_duplicates && schedule.find(&task.name).is_some()
+                {
+                    status(dotfile, &format!("Skipping '{}', which already exists", task.name));
+                    continue;
+                }
+
+                schedule.tasks.push(task);
+                imported += 1;
+            }
+
+            status(dotfile, &format!("Imported {} task{}", imported, if imported == 1 { "" } else { "s" }));
+            status(dotfile, "");
+
+            save_schedule(dotfile, &schedule, save);
+        },
+
+        ("merge", Some(matches)) =>
+        {
+            let other_path = Path::new(matches.value_of("other").unwrap());
+            let other = load_schedule(other_path);
+
+            let mut added = 0;
+            for task in other.tasks
+            {
+                let existing = schedule.tasks.iter().position(|t| t.id == task.id)
+                    .or_else(|| schedule.tasks.iter().position(|t| t.name == task.name));
+
+                let index = match existing
+                {
+                    Some(index) => index,
+                    None =>
+                    {
+                        schedule.tasks.push(task);
+                        added += 1;
+                        continue;
+                    }
+                };
+
+                let ours = &mut schedule.tasks[index];
+                let describe = |task: &Task| task.date_completed.map_or("never completed".to_owned(), |date| format!("completed {}", date.as_naive()));
+
+                if task.date_completed > ours.date_completed
+                {
+                    status(dotfile, &format!("'{}': kept the copy from {} ({}) over this one ({})", task.name, other_path.display(), describe(&task), describe(ours)));
+                    *ours = task;
+                }
+                else if task.date_completed < ours.date_completed
+                {
+                    status(dotfile, &format!("'{}': kept this copy ({}) over the one from {} ({})", ours.name, describe(ours), other_path.display(), describe(&task)));
+                }
+            }
+
+            for task in other.archived
+            {
+                if !schedule.archived.iter().any(|t| t.id == task.id)
+                {
+                    schedule.archived.push(task);
+                }
+            }
+
+            status(dotfile, &format!("Added {} task{} from {}", added, if added == 1 { "" } else { "s" }, other_path.display()));
+            status(dotfile, "");
+
+            save_schedule(dotfile, &schedule, save);
+        },
+
+        ("restore", Some(matches)) =>
+        {
+            let name = matches.value_of("task").unwrap();
+            let index = match_task(&schedule.archived, &doq::index_by_name(&schedule.archived), name, false);
+
+            if schedule.find(&schedule.archived[index].name).is_some()
+            {
+                fail("Task already exists");
+            }
+
+            let mut task = schedule.archived.remove(index);
+            task.date_due = parse_date_or_today(matches.value_of("on"), today).into();
+            schedule.tasks.push(task);
+
+            save_schedule(dotfile, &schedule, save);
+        },
+
+        ("log", Some(matches)) =>
+        {
+            let index = target_task(&schedule.tasks, &name_index, matches, "task");
+            let task = &schedule.tasks[index];
+
+            let mut history: Vec<_> = task.history.iter().map(|completion| completion.date.as_naive()).collect();
+            history.sort_by(|a, b| b.cmp(a));
+
+            if history.is_empty()
+            {
+                println!("No recorded completions of '{}'", task.name);
+            }
+            else
+            {
+                println!("Completions of '{}':", task.name);
+                for date in history
+                {
+                    println!("    {}", date);
+                }
+            }
+            println!();
+        },
+
+        ("undo", Some(matches)) =>
+        {
+            let index = target_task(&schedule.tasks, &name_index, matches, "task");
+            let task_name = schedule.tasks[index].name.clone();
+
+            let undone = {
+                let task = &mut schedule.tasks[index];
+
+                match task.history.last().cloned()
+                {
+                    Some(Completion { date, was_due: Some(was_due) }) =>
+                    {
+                        task.history.pop();
+                        task.date_completed = task.history.last().map(|c| c.date);
+                        task.date_due = was_due;
+                        // Every completion counted down the repetitions left
+                        task.repeat_count = task.repeat_count.map(|count| count + 1);
+                        Some(date)
+                    },
+                    Some(Completion { was_due: None, .. }) => fail("The last completion of this task was recorded without its due date and can't be undone"),
+                    None => None
+                }
+            };
+
+            match undone
+            {
+                Some(date) =>
+                {
+                    save_schedule(dotfile, &schedule, save);
+                    status(dotfile, &format!("Undid completion of '{}' on {}", task_name, date.as_naive()));
+                },
+                None => status(dotfile, &format!("Nothing to undo for '{}'", task_name))
+            }
+            status(dotfile, "");
+        },
+
+        ("snooze", Some(matches)) =>
+        {
+            // With --id there's no name, so a lone value is the duration
+            let duration = matches.value_of("duration").or(matches.value_of("task")).or_fail("No duration given");
+            let index = target_task(&schedule.tasks, &name_index, matches, "task");
+
+            {
+                let task = &mut schedule.tasks[index];
+                let date_due = task.date_due.as_naive();
+                let date_due = doq::offset_from_string(duration, date_due).unwrap_or_else(|e| fail(&e.to_string()));
+                task.date_due = date_due.into();
+            }
+
+            save_schedule(dotfile, &schedule, save);
+        },
+
+        ("reset-overdue", Some(matches)) =>
+        {
+            let date = parse_date_or_today(matches.value_of("to"), today);
+            let overdue: Vec<usize> = schedule.tasks.iter()
+                .enumerate()
+                .filter(|&(_, task)| (all || task.active) && doq::days_until_due(task.date_due.as_naive(), today) < 0)
+                .map(|(index, _)| index)
+                .collect();
+
+            let noun = if overdue.len() == 1 { "task" } else { "tasks" };
+
+            if overdue.is_empty()
+            {
+                status(dotfile, "No overdue tasks");
+            }
+            else if matches.is_present("yes") || confirm(&format!("Move {} overdue {} to {}?", overdue.len(), noun, date))
+            {
+                for &index in &overdue
+                {
+                    schedule.tasks[index].date_due = date.into();
+                }
+
+                save_schedule(dotfile, &schedule, save);
+                status(dotfile, &format!("Moved {} {} to {}", overdue.len(), noun, date));
+            }
+            else
+            {
+                eprintln!("Cancelling");
+            }
+        },
+
+        (subcommand @ "pause", Some(matches)) | (subcommand @ "resume", Some(matches)) =>
+        {
+            let index = target_task(&schedule.tasks, &name_index, matches, "task");
+            let task_name = schedule.tasks[index].name.clone();
+            let active = subcommand == "resume";
+
+            let changed = {
+                let task = &mut schedule.tasks[index];
+                let changed = task.active != active;
+                task.active = active;
+                changed
+            };
+
+            if changed
+            {
+                save_schedule(dotfile, &schedule, save);
+            }
+            else
+            {
+                status(dotfile, &format!("'{}' is already {}", task_name, if active { "active" } else { "paused" }));
+                status(dotfile, "");
+            }
+        },
+
+        ("skip", Some(matches)) =>
+        {
+            let index = target_task(&schedule.tasks, &name_index, matches, "task");
+            let task_name = schedule.tasks[index].name.clone();
+
+            let (date_due, repeat, repeat_until) = {
+                let task = &schedule.tasks[index];
+                (task.date_due.as_naive(), task.repeat, task.repeat_until.as_ref().map(Date::as_naive))
+            };
+
+            match doq::next_due_date(date_due, date_due, repeat).unwrap_or_else(|e| fail(&e.to_string()))
+            {
+                // Past its --until date, the task is finished with, as when it's completed
+                Some(next_due_date) if repeat_until.is_some_and(|until| next_due_date > until) =>
+                {
+                    let task = schedule.tasks.remove(index);
+                    schedule.archived.push(task);
+                    save_schedule(dotfile, &schedule, save);
+                    status(dotfile, &format!("'{}' doesn't repeat after {}, so it's finished and archived", task_name, repeat_until.unwrap()));
+                    status(dotfile, "");
+                },
+                Some(next_due_date) =>
+                {
+                    schedule.tasks[index].date_due = next_due_date.into();
+                    save_schedule(dotfile, &schedule, save);
+                },
+                None =>
+                {
+                    status(dotfile, &format!("'{}' doesn't repeat, so there's nothing to skip", task_name));
+                    status(dotfile, "");
+                }
+            }
+        },
+
+        ("export", Some(sub_matches)) =>
+        {
+            use std::fs::File;
+            use std::io::Write;
+
+            let mut out: Box<dyn Write> = match sub_matches.value_of("output")
+            {
+                Some(path) => Box::new(File::create(path).or_fail("Failed to create output file")),
+                None => Box::new(std::io::stdout())
+            };
+
+            let filter = Filter::from_matches(sub_matches, &matches, all);
+            let mut tasks = sorted_by_due(&schedule.tasks, today);
+            tasks.retain(|&(delta, task)| filter.matches(delta, task));
+
+            let result = if sub_matches.is_present("ics")
+            {
+                write_ics(&mut out, &tasks, today)
+            }
+            else if sub_matches.is_present("markdown")
+            {
+                write_markdown(&mut out, &tasks, today)
+            }
+            else if sub_matches.is_present("html")
+            {
+                write_html(&mut out, &tasks, today, &blocked_tasks(&schedule, &tasks))
+            }
+            else
+            {
+                write_csv(&mut out, &tasks)
+            };
+
+            result.or_fail("Failed to write export");
+            return;
+        },
+
+        ("stats", Some(sub_matches)) =>
+        {
+            let tasks: Vec<&Task> = match sub_matches.value_of("task")
+            {
+                Some(name) =>
+                {
+                    vec![&schedule.tasks[match_task(&schedule.tasks, &name_index, name, false)]]
+                },
+                None => schedule.tasks.iter().collect()
+            };
+
+            match matches.value_of("format").unwrap()
+            {
+                "json" => print_stats_json(&tasks),
+                _ => print_stats(&tasks, sub_matches.is_present("intervals"))
+            }
+            return;
+        },
+        ("diff", Some(sub_matches)) =>
+        {
+            let other_path = Path::new(sub_matches.value_of("other").unwrap());
+            let other = load_schedule(other_path);
+            let diff = diff_schedules(&schedule, &other);
+
+            match matches.value_of("format").unwrap()
+            {
+                "json" => println!("{}", serde_json::to_string_pretty(&diff).or_fail("Failed to serialize diff")),
+                _ => print_diff(&diff, dotfile, other_path)
+            }
+            return;
+        },
+        ("heatmap", Some(sub_matches)) =>
+        {
+            let tasks: Vec<&Task> = match sub_matches.value_of("task")
+            {
+                Some(name) => vec![&schedule.tasks[match_task(&schedule.tasks, &name_index, name, false)]],
+                None => schedule.tasks.iter().collect()
+            };
+
+            print_heatmap(&tasks, today, config.week_start);
+            return;
+        },
+        ("forecast", Some(matches)) =>
+        {
+            let count: usize = matches.value_of("count").unwrap().parse().unwrap();
+            let index = target_task(&schedule.tasks, &name_index, matches, "task");
+            let task = &schedule.tasks[index];
+
+            println!("{} (repeats: {})", task.name, task.repeat);
+            for date in doq::occurrences(task.date_due.as_naive(), task.repeat).take(count)
+            {
+                println!("    {}", date);
+            }
+            return;
+        },
+        ("agenda", Some(sub_matches)) =>
+        {
+            let days: i64 = sub_matches.value_of("days").unwrap().parse().unwrap();
+            let tag = matches.value_of("tag");
+            let tasks: Vec<_> = schedule.tasks.iter()
+                .filter(|task| task.active)
+                .filter(|task| tag.is_none_or(|tag| task.tags.iter().any(|t| t == tag)))
+                .filter(|task| doq::days_until_due(task.date_due.as_naive(), today) <= days)
+                .collect();
+
+            print_agenda(&tasks, today, date_format);
+            return;
+        },
+        ("due", Some(sub_matches)) =>
+        {
+            let date = parse_date(sub_matches.value_of("date").unwrap(), today);
+            let tag = matches.value_of("tag");
+            let tasks: Vec<_> = sorted_by_due(&schedule.tasks, today).into_iter()
+                .filter(|&(_, task)| all || task.active)
+                .filter(|&(_, task)| tag.is_none_or(|tag| task.tags.iter().any(|t| t == tag)))
+                // Overdue tasks are still waiting to be done today
+                .filter(|&(delta, task)| (date == today && delta < 0) || doq::occurs_on(task.date_due.as_naive(), task.repeat, date))
+                .collect();
+
+            let date_string = date.format(date_format);
+            match tasks.is_empty()
+            {
+                true => println!("Nothing due on {}", date_string),
+                false =>
+                {
+                    println!("Due on {}", date_string);
+                    for (delta, task) in tasks
+                    {
+                        match date == today && delta < 0
+                        {
+                            true => println!("    {} (overdue)", task.name),
+                            false => println!("    {}", task.name)
+                        }
+                    }
+                }
+            }
+            return;
+        },
+        ("next", Some(sub_matches)) =>
+        {
+            let days: i64 = sub_matches.value_of("days").unwrap().parse().unwrap();
+            let tag = matches.value_of("tag");
+            let tasks = sorted_by_due(schedule.tasks.iter().filter(|task| task.active), today);
+            let next = tasks.into_iter()
+                .filter(|&(_, task)| tag.is_none_or(|tag| task.tags.iter().any(|t| t == tag)))
+                .find(|&(delta, _)| delta <= days);
+
+            match matches.value_of("format").unwrap()
+            {
+                "json" => print_schedule_json(next.as_slice(), today),
+                _ => match next
+                {
+                    Some((delta, task)) =>
+                    {
+                        let status = match delta
+                        {
+                            0 => "due today".to_owned(),
+                            1 => "due tomorrow".to_owned(),
+                            -1 => "1 day overdue".to_owned(),
+                            n if n < 0 => format!("{} days overdue", -n),
+                            n => format!("due in {} days", n)
+                        };
+                        println!("{}: {}", task.name, status);
+                    },
+                    None => println!("nothing due")
+                }
+            }
+            return;
+        },
+        ("projects", Some(_)) =>
+        {
+            let summaries = project_summaries(schedule.tasks.iter().filter(|task| all || task.active), today);
+
+            match matches.value_of("format").unwrap()
+            {
+                "json" => println!("{}", serde_json::to_string_pretty(&summaries).or_fail("Failed to serialize projects")),
+                _ if summaries.is_empty() => println!("No projects"),
+                _ => for summary in &summaries
+                {
+                    let overdue = match summary.overdue
+                    {
+                        0 => "none overdue".to_owned(),
+                        n => format!("{} overdue", n)
+                    };
+                    println!("{}: {} task{}, {}", summary.name, summary.tasks, if summary.tasks == 1 { "" } else { "s" }, overdue);
+                }
+            }
+            return;
+        },
+        #[cfg(feature = "notify")]
+        ("notify", Some(sub_matches)) =>
+        {
+            let tag = matches.value_of("tag");
+            let tasks = sorted_by_due(schedule.tasks.iter().filter(|task| task.active), today);
+            let tasks: Vec<_> = tasks.into_iter()
+                .filter(|&(delta, task)| delta <= 0 && tag.is_none_or(|tag| task.tags.iter().any(|t| t == tag)))
+                .collect();
+
+            if !tasks.is_empty() && !show_notifications(&tasks, sub_matches.is_present("individual"))
+            {
+                print!("{}", digest(&tasks));
+            }
+            return;
+        },
+        ("digest", Some(_)) =>
+        {
+            let tag = matches.value_of("tag");
+            let tasks = sorted_by_due(schedule.tasks.iter().filter(|task| task.active), today);
+            let tasks: Vec<_> = tasks.into_iter()
+                .filter(|&(_, task)| tag.is_none_or(|tag| task.tags.iter().any(|t| t == tag)))
+                .collect();
+
+            print!("{}", digest(&tasks));
+            return;
+        },
+        ("calendar", Some(sub_matches)) =>
+        {
+            let month = match sub_matches.value_of("month")
+            {
+                Some(month) => parse_month(month).unwrap(),
+                None => today.with_day(1).unwrap()
+            };
+
+            print_calendar(&schedule.tasks, month, today, config.week_start);
+            return;
+        },
+        ("watch", Some(sub_matches)) =>
+        {
+            if is_stdio(dotfile)
+            {
+                fail("Cannot watch stdin");
+            }
+
+            let interval = std::time::Duration::from_secs(sub_matches.value_of("interval").unwrap().parse().unwrap());
+            let style = ListingStyle::from_matches(&matches, &config, date_format);
+            let filter = Filter
+            {
+                all,
+                tag: matches.value_of("tag"),
+                project: matches.value_of("project"),
+                due_within: if all { None } else { config.default_horizon_days },
+                ..Filter::default()
+            };
+
+            // Only redraw when the file or the date changes, to avoid flicker
+            let mut last_seen = None;
+            loop
+            {
+                let today = doq::today(config.timezone.as_deref()).unwrap_or_else(|e| fail(&e.to_string()));
+                let modified = std::fs::metadata(dotfile).and_then(|metadata| metadata.modified()).ok();
+                let seen = Some((today, modified));
+
+                if seen != last_seen
+                {
+                    last_seen = seen;
+
+                    // Clear the screen and move the cursor to the top left
+                    print!("\x1b[2J\x1b[H");
+                    match try_load_schedule(dotfile)
+                    {
+                        Ok(schedule) =>
+                        {
+                            let mut tasks = sorted_by_due(&schedule.tasks, today);
+                            tasks.retain(|&(delta, task)| filter.matches(delta, task));
+                            print_listing(&schedule, &tasks, today, &style, true);
+                        },
+                        Err(e) => println!("doq: error: {} in {}", e, dotfile.display())
+                    }
+
+                    use std::io::Write;
+                    let _ = std::io::stdout().flush();
+                }
+
+                std::thread::sleep(interval);
+            }
+        },
+        ("list", Some(_)) => (),
+        _ => ()
+    }
+
+    let mut filter = match matches.subcommand()
+    {
+        ("list", Some(list_matches)) => Filter::from_matches(list_matches, &matches, all),
+        _ => Filter { all, tag: matches.value_of("tag"), project: matches.value_of("project"), ..Filter::default() }
+    };
+
+    if !all && filter.due_within.is_none()
+    {
+        filter.due_within = config.default_horizon_days;
+    }
+
+    let mut tasks = sorted_by_due(&schedule.tasks, today);
+    tasks.retain(|&(delta, task)| filter.matches(delta, task));
+
+    if matches.value_of("sort") == Some("priority")
+    {
+        tasks.sort_by_key(|&(delta, task)| (std::cmp::Reverse(task.priority), delta));
+    }
+
+    let quiet = matches.is_present("quiet") || matches.subcommand().1.is_some_and(|m| m.is_present("quiet"));
+    let show_schedule = match matches.subcommand_name()
+    {
+        None | Some("list") => !(quiet && matches.is_present("check")),
+        Some(_) => !quiet && !is_stdio(dotfile)
+    };
+
+    if show_schedule
+    {
+        match matches.value_of("format").unwrap()
+        {
+            _ if matches.is_present("porcelain") => print_schedule_porcelain(&tasks, today),
+            "json" => print_schedule_json(&tasks, today),
+            _ => print_listing(&schedule, &tasks, today, &ListingStyle::from_matches(&matches, &config, date_format), !quiet)
+        }
+    }
+
+    if matches.is_present("check") && tasks.iter().any(|&(delta, task)| task.active && delta < 0 && schedule.blocked_by(task).is_none())
+    {
+        std::process::exit(2);
+    }
+}
+
+
+#[derive(Default)]
+struct Filter<'a>
+{
+    all: bool,
+    tag: Option<&'a str>,
+    project: Option<&'a str>,
+    overdue: bool,
+    due_today: bool,
+    due_within: Option<i64>
+}
+
+impl<'a> Filter<'a>
+{
+    /// Reads the filters from `filter_args`, falling back to the top-level
+    /// `--tag` and `--project`.
+    fn from_matches(matches: &'a clap::ArgMatches, top_level: &'a clap::ArgMatches, all: bool) -> Filter<'a>
+    {
+        Filter
+        {
+            all,
+            tag: matches.value_of("tag").or(top_level.value_of("tag")),
+            project: matches.value_of("project").or(top_level.value_of("project")),
+            overdue: matches.is_present("overdue"),
+            due_today: matches.is_present("due_today"),
+            due_within: matches.value_of("due_within").map(|days| days.parse().unwrap())
+        }
+    }
+
+    /// Status flags widen the filter (overdue or due today), while the tag,
+    /// project and due-within limits narrow it. Paused tasks only match with `all`.
+    fn matches(&self, delta: i64, task: &Task) -> bool
+    {
+        let status_matches = match (self.overdue, self.due_today)
+        {
+            (false, false) => true,
+            (overdue, due_today) => (overdue && delta < 0) || (due_today && delta == 0)
+        };
+
+        status_matches
+            && (self.all || task.active)
+            && self.due_within.is_none_or(|days| delta <= days)
+            && self.tag.is_none_or(|tag| task.tags.iter().any(|t| t == tag))
+            && self.project.is_none_or(|project| task.project.as_deref() == Some(project))
+    }
+}
+
+
+fn sorted_by_due<'a, I>(tasks: I, today: NaiveDate) -> Vec<(i64, &'a Task)>
+where
+    I: IntoIterator<Item = &'a Task>
+{
+    let mut delta_tasks: Vec<_> = tasks.into_iter().map(
+        |task|
+        {
+            let date_due = task.date_due.as_naive();
+            let delta = doq::days_until_due(date_due, today);
+            (delta, task)
+        }).collect();
+    delta_tasks.sort_by(|&(a, task_a), &(b, task_b)| (a, &task_a.name).cmp(&(b, &task_b.name)));
+    delta_tasks
+}
+
+fn use_color(matches: &clap::ArgMatches) -> bool
+{
+    use std::io::IsTerminal;
+
+    match (matches.is_present("no_color"), matches.value_of("color"))
+    {
+        (true, _) | (_, Some("never")) => false,
+        (_, Some("always")) => true,
+        _ => std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && std::io::stdout().is_terminal()
+    }
+}
+
+/// How `print_schedule` lays out the listing.
+struct ListingStyle<'a>
+{
+    show_notes: bool,
+    show_symbols: bool,
+    color_enabled: bool,
+    warn_within_days: Option<u32>,
+    date_format: &'a str,
+    group_by_tag: bool,
+    show_intervals: bool
+}
+
+impl<'a> ListingStyle<'a>
+{
+    fn from_matches(matches: &clap::ArgMatches, config: &AppConfig, date_format: &'a str) -> ListingStyle<'a>
+    {
+        ListingStyle
+        {
+            show_notes: matches.is_present("notes"),
+            show_symbols: matches.is_present("symbols"),
+            color_enabled: use_color(matches),
+            warn_within_days: config.warn_within_days,
+            date_format,
+            group_by_tag: matches.value_of("group_by") == Some("tag"),
+            show_intervals: matches.subcommand_matches("list").is_some_and(|list| list.is_present("intervals"))
+        }
+    }
+}
+
+/// Prints the tasks from `schedule` as a table, optionally followed by a summary.
+fn print_listing(schedule: &Schedule, tasks: &[(i64, &Task)], today: NaiveDate, style: &ListingStyle, show_summary: bool)
+{
+    let blocked = blocked_tasks(schedule, tasks);
+
+    print_schedule(tasks, today, &blocked, style);
+    if show_summary
+    {
+        print_summary(tasks, today, &blocked);
+    }
+}
+
+/// Maps each of `tasks` that is waiting on another to the name of that task.
+fn blocked_tasks<'a>(schedule: &'a Schedule, tasks: &[(i64, &Task)]) -> std::collections::HashMap<Uuid, &'a str>
+{
+    tasks.iter()
+        .filter_map(|&(_, task)| schedule.blocked_by(task).map(|prerequisite| (task.id, prerequisite)))
+        .collect()
+}
+
+/// Prints the tasks as a table. Tasks in `blocked` are greyed out, with the
+/// name of the task they're waiting on.
+fn print_schedule(tasks: &[(i64, &Task)], today: NaiveDate, blocked: &std::collections::HashMap<Uuid, &str>, style: &ListingStyle)
+{
+    let red = Color::Fixed(9);
+    let green = Color::Fixed(10);
+    let yellow = Color::Fixed(11);
+    let white = Color::Fixed(15);
+    let grey = Color::Fixed(8);
+
+    let days_ago = |task: &Task| task.date_completed.map(|date| today.signed_duration_since(date.as_naive()).num_days());
+    let days_width = tasks.iter()
+        .filter_map(|&(_, task)| days_ago(task))
+        .map(|days| days.to_string().len())
+        .max()
+        .unwrap_or(1);
+    let due_width = tasks.iter()
+        .map(|&(delta, _)| delta.abs().to_string().len())
+        .max()
+        .unwrap_or(1);
+
+    let rows: Vec<_> = tasks.iter().map(|&(_, task)|
+    {
+        let leader = if task.at_least { '<' } else { ' ' };
+
+        let freq_string = match (task.repeat, task.repeat_count)
+        {
+            (Repeat::Never, _) => "--".to_owned(),
+            (repeat, Some(count)) => format!("{}{} ({} left)", leader, repeat, count),
+            (repeat, None) => format!("{}{}", leader, repeat)
+        };
+        let freq_string = match style.show_intervals
+        {
+            true => format!("{} ({})", freq_string, intervals(&doq::stats(&task.history))),
+            false => freq_string
+        };
+
+        let (datestring, days_ago_text) = match (task.date_completed, days_ago(task))
+        {
+            (Some(date), Some(days)) =>
+            {
+                let days_ago_text = match days
+                {
+                    0 => format!("{: >w$} Today", "", w = days_width),
+                    1 => format!("{: >w$} day ago", 1, w = days_width),
+                    n => format!("{: >w$} days ago", n, w = days_width)
+                };
+
+                (date.as_naive().format(style.date_format).to_string(), days_ago_text)
+            },
+            _ => ("Never".to_owned(), "".to_owned())
+        };
+
+        let date_due = task.date_due.as_naive();
+        let due_date_string = date_due.format(style.date_format).to_string();
+
+        let upcoming_color = |n: i64| match style.warn_within_days
+        {
+            Some(warn) if n <= warn as i64 => yellow,
+            _ if n == 1 => white,
+            _ => green
+        };
+
+        let (color, symbol, status) = match doq::status(date_due, today)
+        {
+            TaskStatus::Upcoming(1) => (upcoming_color(1), ' ', "(Due tomorrow)".to_owned()),
+            TaskStatus::Upcoming(n) => (upcoming_color(n), ' ', format!("(Due in {: >w$} days)", n, w = due_width)),
+            TaskStatus::DueToday => (yellow, '*', "(Due today)".to_owned()),
+            TaskStatus::Overdue(1) => (red, '!', format!("({: >w$} day overdue!)", 1, w = due_width)),
+            TaskStatus::Overdue(n) => (red, '!', format!("({: >w$} days overdue!)", n, w = due_width))
+        };
+        let (color, symbol, status) = match (task.active, blocked.get(&task.id))
+        {
+            (false, _) => (grey, '~', "(Paused)".to_owned()),
+            (true, Some(prerequisite)) => (grey, '~', format!("(Waiting on {})", prerequisite)),
+            (true, None) => (color, symbol, status)
+        };
+
+        let name = match task.tags.len()
+        {
+            0 => task.name.clone(),
+            _ => format!("{} [{}]", task.name, task.tags.join(", "))
+        };
+
+        let priority = match task.priority
+        {
+            0 => String::new(),
+            p => p.to_string()
+        };
+
+        ([name, freq_string, priority, datestring, days_ago_text, due_date_string, status], color, symbol, task)
+    }).collect();
+
+    let mut widths = [4, 0, 3, 0, 0, 0, 0];
+    for (cells, _, _, _) in &rows
+    {
+        for (width, cell) in widths.iter_mut().zip(cells)
+        {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let margin = if style.show_symbols { "  " } else { "" };
+
+    // Without a known terminal width, keep the full layout
+    let show_days_ago = match term_size::dimensions_stdout()
+    {
+        Some((terminal_width, _)) => fit_columns(&mut widths, terminal_width.saturating_sub(margin.len())),
+        None => true
+    };
+
+    // "Last completed" and "Due on" each head a pair of columns
+    let (completed_header, completed_width) = completed_column(&widths, show_days_ago);
+    let due_width = widths[5] + 1 + widths[6];
+
+    let header = format!(
+        "{: <w0$} {: >w1$} {: >w2$}  {: <cw$} {: <dw$}", "Task", "", "Pri", completed_header, "Due on",
+        w0 = widths[0], w1 = widths[1], w2 = widths[2], cw = completed_width, dw = due_width);
+    let underline = format!(
+        "{: <w0$} {: >w1$} {: >w2$}  {: <cw$} {: <dw$}", "===", "", "===", "===", "===",
+        w0 = widths[0], w1 = widths[1], w2 = widths[2], cw = completed_width, dw = due_width);
+
+    println!("{}{}", margin, header.trim_end());
+    if style.show_symbols
+    {
+        println!("{}(! overdue, * due today, ~ paused)", margin);
+    }
+    println!("{}{}", margin, underline.trim_end());
+
+    let print_row = |&(ref cells, color, symbol, task): &([String; 7], Color, char, &Task)|
+    {
+        let completed = match show_days_ago
+        {
+            true => format!("{: <w3$} {}", cells[3], cells[4], w3 = widths[3]),
+            false => cells[3].clone()
+        };
+        let line = format!(
+            "{: <w0$} {: >w1$} {: >w2$}  {: <cw$} {: <w5$} {}",
+            truncate(&cells[0], widths[0]), cells[1], cells[2], completed, cells[5], cells[6],
+            w0 = widths[0], w1 = widths[1], w2 = widths[2], cw = completed_width, w5 = widths[5]);
+        let line = match style.show_symbols
+        {
+            true => format!("{} {}", symbol, line.trim_end()),
+            false => line.trim_end().to_owned()
+        };
+
+        match style.color_enabled
+        {
+            true => println!("{}", color.paint(line)),
+            false => println!("{}", line)
+        }
+
+        if let (true, Some(note)) = (style.show_notes, task.note.as_ref())
+        {
+            let wrapper = textwrap::Wrapper::new(textwrap::termwidth())
+                .initial_indent("    ")
+                .subsequent_indent("    ");
+
+            println!("{}", wrapper.fill(note));
+        }
+    };
+
+    match style.group_by_tag
+    {
+        false => rows.iter().for_each(print_row),
+        true =>
+        {
+            // Tasks with several tags are listed under each of them
+            let mut tags: Vec<&str> = rows.iter().flat_map(|row| row.3.tags.iter().map(String::as_str)).collect();
+            tags.sort();
+            tags.dedup();
+
+            let groups = tags.into_iter().map(Some).chain(Some(None));
+            for tag in groups
+            {
+                let group: Vec<_> = rows.iter()
+                    .filter(|row| match tag
+                    {
+                        Some(tag) => row.3.tags.iter().any(|t| t == tag),
+                        None => row.3.tags.is_empty()
+                    })
+                    .collect();
+
+                if group.is_empty()
+                {
+                    continue;
+                }
+
+                println!();
+                println!("{}{}", margin, tag.map_or("(no tag)".to_owned(), |tag| format!("[{}]", tag)));
+                group.into_iter().for_each(print_row);
+            }
+        }
+    }
+}
+
+
+/// Prints a line counting the tasks that are overdue, due today, and upcoming.
+fn print_summary(tasks: &[(i64, &Task)], today: NaiveDate, blocked: &std::collections::HashMap<Uuid, &str>)
+{
+    println!();
+    println!("{}", summary(tasks, today, blocked));
+}
+
+
+/// Counts the tasks that are overdue, due today, and upcoming. Tasks in
+/// `blocked` can't be done yet, so they're counted as waiting instead.
+fn summary(tasks: &[(i64, &Task)], today: NaiveDate, blocked: &std::collections::HashMap<Uuid, &str>) -> String
+{
+    let (mut overdue, mut due_today, mut upcoming, mut paused, mut waiting) = (0, 0, 0, 0, 0);
+
+    for &(_, task) in tasks
+    {
+        match doq::status(task.date_due.as_naive(), today)
+        {
+            _ if !task.active => paused += 1,
+            _ if blocked.contains_key(&task.id) => waiting += 1,
+            TaskStatus::Overdue(_) => overdue += 1,
+            TaskStatus::DueToday => due_today += 1,
+            TaskStatus::Upcoming(_) => upcoming += 1
+        }
+    }
+
+    let mut summary = format!("{} overdue, {} due today, {} upcoming", overdue, due_today, upcoming);
+    if paused > 0
+    {
+        summary += &format!(", {} paused", paused);
+    }
+    if waiting > 0
+    {
+        summary += &format!(", {} waiting", waiting);
+    }
+
+    summary
+}
+
+/// The header and width of the column pair for when a task was last completed.
+fn completed_column(widths: &[usize; 7], show_days_ago: bool) -> (&'static str, usize)
+{
+    match show_days_ago
+    {
+        true => ("Last completed", (widths[3] + 1 + widths[4]).max("Last completed".len())),
+        false => ("Completed", widths[3].max("Completed".len()))
+    }
+}
+
+/// Shrinks the schedule table to fit within `max_width` characters, first by
+/// dropping the "days ago" column, then by narrowing the task name column down
+/// to a minimum. Returns whether the "days ago" column is still shown.
+fn fit_columns(widths: &mut [usize; 7], max_width: usize) -> bool
+{
+    const MIN_NAME_WIDTH: usize = 8;
+
+    let table_width = |widths: &[usize; 7], show_days_ago: bool|
+        widths[0] + 1 + widths[1] + 1 + widths[2] + 2 + completed_column(widths, show_days_ago).1 + 1 + widths[5] + 1 + widths[6];
+
+    let show_days_ago = table_width(widths, true) <= max_width;
+    let overflow = table_width(widths, show_days_ago).saturating_sub(max_width);
+    widths[0] = widths[0].saturating_sub(overflow).max(widths[0].min(MIN_NAME_WIDTH));
+
+    show_days_ago
+}
+
+/// Cuts `text` down to `width` characters, ending in an ellipsis if anything
+/// was cut.
+fn truncate(text: &str, width: usize) -> String
+{
+    match text.chars().count() <= width
+    {
+        true => text.to_owned(),
+        false => text.chars().take(width.saturating_sub(1)).chain(Some('…')).collect()
+    }
+}
+
+fn print_archive(tasks: &[Task], date_format: &str)
+{
+    if tasks.is_empty()
+    {
+        println!("No archived tasks");
+        return;
+    }
+
+    let width = tasks.iter().map(|task| task.name.chars().count()).chain(Some(4)).max().unwrap();
+
+    println!("{: <w$} Completed", "Task", w = width);
+    println!("{: <w$} ===", "===", w = width);
+
+    for task in tasks
+    {
+        let completed = match task.date_completed
+        {
+            Some(date) => date.as_naive().format(date_format).to_string(),
+            None => "Never".to_owned()
+        };
+
+        println!("{: <w$} {}", task.name, completed, w = width);
+    }
+}
+
+fn print_agenda(tasks: &[&Task], today: NaiveDate, date_format: &str)
+{
+    // Overdue tasks all share one group, so clamp them to a single key
+    let mut tasks: Vec<_> = tasks.iter()
+        .map(|task| (doq::days_until_due(task.date_due.as_naive(), today).max(-1), *task))
+        .collect();
+    tasks.sort_by(|&(a, task_a), &(b, task_b)| (a, &task_a.name).cmp(&(b, &task_b.name)));
+
+    let mut current_group = None;
+
+    for (group, task) in tasks
+    {
+        let date_due = task.date_due.as_naive();
+
+        if current_group != Some(group)
+        {
+            if current_group.is_some()
+            {
+                println!();
+            }
+
+            let header = match doq::status(date_due, today)
+            {
+                TaskStatus::Overdue(_) => "Overdue".to_owned(),
+                TaskStatus::DueToday => "Today".to_owned(),
+                TaskStatus::Upcoming(1) => "Tomorrow".to_owned(),
+                // Named by weekday whatever the date format, as the agenda is read by day
+                TaskStatus::Upcoming(_) => format!("{} {}", date_due.format("%A"), date_due.format(date_format))
+            };
+
+            println!("{}", header);
+            current_group = Some(group);
+        }
+
+        match doq::status(date_due, today)
+        {
+            TaskStatus::Overdue(1) => println!("    {} (1 day overdue!)", task.name),
+            TaskStatus::Overdue(n) => println!("    {} ({} days overdue!)", task.name, n),
+            _ => println!("    {}", task.name)
+        }
+    }
+}
+
+/// Two letter names for each day of the week, starting from `week_start`.
+fn weekday_names(week_start: Weekday) -> Vec<&'static str>
+{
+    let names = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+    let first_day = week_start.num_days_from_monday() as usize;
+    (0..7).map(|day| names[(first_day + day) % 7]).collect()
+}
+
+/// How many days `date` is after the start of its week.
+fn days_into_week(date: NaiveDate, week_start: Weekday) -> usize
+{
+    (date.weekday().num_days_from_monday() as usize + 7 - week_start.num_days_from_monday() as usize) % 7
+}
+
+/// Prints a grid of how many of `tasks` were completed each day over the last
+/// year, with a column per week and a row per day of the week.
+fn print_heatmap(tasks: &[&Task], today: NaiveDate, week_start: Weekday)
+{
+    const WEEKS: usize = 53;
+
+    let first = today - Duration::days(days_into_week(today, week_start) as i64) - Duration::weeks(WEEKS as i64 - 1);
+
+    let mut counts = std::collections::HashMap::new();
+    for task in tasks
+    {
+        for completion in &task.history
+        {
+            let date = completion.date.as_naive();
+            if date >= first && date <= today
+            {
+                *counts.entry(date).or_insert(0) += 1;
+            }
+        }
+    }
+
+    // Label each month above the week it starts in, where there's room
+    let mut months = vec![' '; WEEKS];
+    let mut free_from = 0;
+    for week in 0..WEEKS
+    {
+        let date = first + Duration::weeks(week as i64);
+        let starts_month = week == 0 || (date - Duration::weeks(1)).month() != date.month();
+        let label = date.format("%b").to_string();
+
+        if starts_month && week >= free_from && week + label.len() <= WEEKS
+        {
+            months[week..week + label.len()].iter_mut().zip(label.chars()).for_each(|(cell, c)| *cell = c);
+            free_from = week + label.len() + 1;
+        }
+    }
+    println!("{}", format!("   {}", months.into_iter().collect::<String>()).trim_end());
+
+    for (day, name) in weekday_names(week_start).into_iter().enumerate()
+    {
+        let cells: String = (0..WEEKS)
+            .map(|week| first + Duration::days((week * 7 + day) as i64))
+            .take_while(|&date| date <= today)
+            .map(|date| match counts.get(&date).cloned().unwrap_or(0)
+            {
+                0 => '·',
+                1 => '░',
+                2 => '▒',
+                3 => '▓',
+                _ => '█'
+            })
+            .collect();
+
+        println!("{} {}", name, cells);
+    }
+
+    println!();
+    match counts.values().sum::<usize>()
+    {
+        1 => println!("1 completion in the last year"),
+        n => println!("{} completions in the last year", n)
+    }
+    println!("Less · ░ ▒ ▓ █ More");
+}
+
+/// A line listing overdue tasks and a line listing tasks due today, leaving
+/// out either if it would be empty.
+fn digest(tasks: &[(i64, &Task)]) -> String
+{
+    let overdue: Vec<_> = tasks.iter()
+        .filter(|&&(delta, _)| delta < 0)
+        .map(|&(delta, task)| match -delta
+        {
+            1 => format!("{} (1 day)", task.name),
+            n => format!("{} ({} days)", task.name, n)
+        })
+        .collect();
+    let due_today: Vec<_> = tasks.iter()
+        .filter(|&&(delta, _)| delta == 0)
+        .map(|&(_, task)| task.name.as_str())
+        .collect();
+
+    let mut digest = String::new();
+    if !overdue.is_empty()
+    {
+        digest += &format!("Overdue: {}\n", overdue.join(", "));
+    }
+    if !due_today.is_empty()
+    {
+        digest += &format!("Due today: {}\n", due_today.join(", "));
+    }
+    digest
+}
+
+/// Runs the `on_complete` hook for a task with `sh`, filling in its
+/// placeholders with shell-quoted values. The completion is already saved,
+/// so failures are only warned about. With `to_stderr` the hook's output goes
+/// to stderr, to keep it out of a schedule written to stdout.
+fn run_completion_hook(template: &str, name: &str, date: NaiveDate, date_due: NaiveDate, outcome: Outcome, to_stderr: bool)
+{
+    let next = match outcome
+    {
+        Outcome::Rescheduled(next_due_date) => next_due_date.to_string(),
+        Outcome::Finished => "never".to_owned()
+    };
+    let values = [("name", name.to_owned()), ("date", date.to_string()), ("due", date_due.to_string()), ("next", next)];
+
+    // Fill in every placeholder in one pass, so values are never themselves expanded
+    let mut command = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{')
+    {
+        command.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let placeholder = values.iter().find(|(key, _)| rest[1..].starts_with(key) && rest[1 + key.len()..].starts_with('}'));
+        match placeholder
+        {
+            Some((key, value)) =>
+            {
+                command.push_str(&format!("'{}'", value.replace('\'', "'\\''")));
+                rest = &rest[key.len() + 2..];
+            },
+            None =>
+            {
+                command.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    command.push_str(rest);
+
+    let mut hook = std::process::Command::new("sh");
+    hook.arg("-c").arg(&command);
+    if to_stderr
+    {
+        hook.stdout(std::io::stderr());
+    }
+
+    match hook.status()
+    {
+        Ok(status) if status.success() => (),
+        Ok(status) => warn(&format!("on_complete hook for '{}' failed ({})", name, status)),
+        Err(e) => warn(&format!("Couldn't run on_complete hook for '{}': {}", name, e))
+    }
+}
+
+/// Shows desktop notifications for the overdue tasks and those due today:
+/// one listing them all, or one for each with `individual`. Returns false if
+/// notifications can't be shown, as on a headless system.
+#[cfg(feature = "notify")]
+fn show_notifications(tasks: &[(i64, &Task)], individual: bool) -> bool
+{
+    use notify_rust::Notification;
+
+    let notifications: Vec<(String, String)> = match individual
+    {
+        true => tasks.iter().map(|&(delta, task)|
+        {
+            let status = match delta
             {
-                fail("Task already exists");
+                0 => "Due today".to_owned(),
+                -1 => "1 day overdue".to_owned(),
+                n => format!("{} days overdue", -n)
+            };
+            (task.name.clone(), status)
+        }).collect(),
+        false =>
+        {
+            let overdue = tasks.iter().filter(|&&(delta, _)| delta < 0).count();
+            let due_today = tasks.len() - overdue;
+            vec![(format!("{} overdue, {} due today", overdue, due_today), digest(tasks).trim_end().to_owned())]
+        }
+    };
+
+    notifications.iter().all(|(summary, body)|
+    {
+        Notification::new().appname("doq").summary(summary).body(body).show().is_ok()
+    })
+}
+
+/// Prints a grid of the month starting at `month`, with weeks starting on
+/// `week_start`. Days with a task due are marked with `*`, or `!` if that task
+/// is now overdue.
+fn print_calendar(tasks: &[Task], month: NaiveDate, today: NaiveDate, week_start: Weekday)
+{
+    let next_month = doq::add_months(month, 1).or_fail("Month is out of range");
+    let days_in_month = next_month.signed_duration_since(month).num_days() as usize;
+    let mut markers = vec![' '; days_in_month];
+
+    for task in tasks.iter().filter(|task| task.active)
+    {
+        let date_due = task.date_due.as_naive();
+        let upcoming = doq::occurrences(date_due, task.repeat).take_while(|&date| date < next_month);
+
+        for date in upcoming.filter(|&date| date >= month)
+        {
+            let marker = &mut markers[date.day0() as usize];
+            let overdue = date == date_due && date < today;
+            *marker = if overdue || *marker == '!' { '!' } else { '*' };
+        }
+    }
+
+    println!("{}", format!("{:^27}", month.format("%B %Y").to_string()).trim_end());
+    println!("{}", weekday_names(week_start).join("  "));
+
+    let padding = days_into_week(month, week_start);
+    let cells: Vec<String> = std::iter::repeat_n(String::new(), padding)
+        .chain(markers.iter().enumerate().map(|(day, marker)| format!("{: >2}{}", day + 1, marker)))
+        .collect();
+
+    for week in cells.chunks(7)
+    {
+        let week: Vec<_> = week.iter().map(|cell| format!("{: <3}", cell)).collect();
+        println!("{}", week.join(" ").trim_end());
+    }
+
+    println!();
+    println!("* due  ! overdue");
+}
+
+fn print_schedule_json(tasks: &[(i64, &Task)], today: NaiveDate)
+{
+    #[derive(Serialize)]
+    struct TaskSummary<'a>
+    {
+        id: Uuid,
+        name: &'a str,
+        date_due: &'a Date,
+        date_completed: &'a Option<Date>,
+        repeat: Repeat,
+        at_least: bool,
+        tags: &'a [String],
+        project: &'a Option<String>,
+        depends_on: &'a Option<String>,
+        priority: u8,
+        note: &'a Option<String>,
+        active: bool,
+        days_until_due: i64,
+        status: &'static str
+    }
+
+    let summaries: Vec<_> = tasks.iter().map(
+        |&(delta, task)|
+        {
+            let status = match doq::status(task.date_due.as_naive(), today)
+            {
+                TaskStatus::Overdue(_) => "overdue",
+                TaskStatus::DueToday => "due_today",
+                TaskStatus::Upcoming(_) => "upcoming"
+            };
+
+            TaskSummary
+            {
+                id: task.id,
+                name: &task.name,
+                date_due: &task.date_due,
+                date_completed: &task.date_completed,
+                repeat: task.repeat,
+                at_least: task.at_least,
+                tags: &task.tags,
+                project: &task.project,
+                depends_on: &task.depends_on,
+                priority: task.priority,
+                note: &task.note,
+                active: task.active,
+                days_until_due: delta,
+                status
+            }
+        }).collect();
+
+    println!("{}", serde_json::to_string_pretty(&summaries).or_fail("Failed to serialize schedule"));
+}
+
+
+/// Prints one line per task with tab-separated fields, in this order:
+/// name, repeat, due date, completed date (or `-`), days until due, and
+/// status (`overdue`, `due_today`, `upcoming`, or `paused`). Scripts rely on
+/// this, so new fields only ever go on the end.
+fn print_schedule_porcelain(tasks: &[(i64, &Task)], today: NaiveDate)
+{
+    for &(delta, task) in tasks
+    {
+        let status = match doq::status(task.date_due.as_naive(), today)
+        {
+            _ if !task.active => "paused",
+            TaskStatus::Overdue(_) => "overdue",
+            TaskStatus::DueToday => "due_today",
+            TaskStatus::Upcoming(_) => "upcoming"
+        };
+        let completed = task.date_completed.map_or("-".to_owned(), |date| date.as_naive().to_string());
+
+        println!("{}\t{}\t{}\t{}\t{}\t{}", task.name, task.repeat, task.date_due.as_naive(), completed, delta, status);
+    }
+}
+
+
+fn print_stats(tasks: &[&Task], show_intervals: bool)
+{
+    for task in tasks
+    {
+        let stats = doq::stats(&task.history);
+        let average_interval = match stats.average_interval
+        {
+            Some(days) => format!("{:.1} days", days),
+            None => "--".to_owned()
+        };
+
+        println!("{}", task.name);
+        println!("    Completions:      {}", stats.completions);
+        println!("    On time:          {}", stats.on_time);
+        println!("    Late:             {}", stats.late);
+        println!("    Average interval: {}", average_interval);
+        println!("    On-time streak:   {}", stats.streak);
+        if show_intervals
+        {
+            println!("    Intervals:        {} (repeats {})", intervals(&stats), task.repeat);
+        }
+        println!();
+    }
+}
+
+/// The mean and median days between completions, or a dash without enough
+/// completions to tell.
+fn intervals(stats: &Stats) -> String
+{
+    match (stats.average_interval, stats.median_interval)
+    {
+        (Some(mean), Some(median)) => format!("mean {:.1}, median {:.1} days", mean, median),
+        _ => "—".to_owned()
+    }
+}
+
+fn print_stats_json(tasks: &[&Task])
+{
+    #[derive(Serialize)]
+    struct TaskStats<'a>
+    {
+        name: &'a str,
+        stats: Stats
+    }
+
+    let stats: Vec<_> = tasks.iter().map(|task| TaskStats { name: &task.name, stats: doq::stats(&task.history) }).collect();
+
+    println!("{}", serde_json::to_string_pretty(&stats).or_fail("Failed to serialize stats"));
+}
+
+
+#[derive(Serialize)]
+struct ProjectSummary<'a>
+{
+    name: &'a str,
+    tasks: usize,
+    overdue: usize
+}
+
+/// Counts the tasks in each project, and how many are overdue, sorted by project name.
+fn project_summaries<'a, I>(tasks: I, today: NaiveDate) -> Vec<ProjectSummary<'a>>
+where
+    I: IntoIterator<Item = &'a Task>
+{
+    let mut projects = std::collections::BTreeMap::new();
+    for task in tasks
+    {
+        if let Some(ref project) = task.project
+        {
+            let summary = projects.entry(project.as_str()).or_insert(ProjectSummary { name: project, tasks: 0, overdue: 0 });
+            summary.tasks += 1;
+            if doq::days_until_due(task.date_due.as_naive(), today) < 0
+            {
+                summary.overdue += 1;
+            }
+        }
+    }
+    projects.into_values().collect()
+}
+
+
+/// How two schedules differ. Tasks are paired up by id, or by name where
+/// the ids don't match.
+#[derive(Serialize)]
+struct ScheduleDiff<'a>
+{
+    only_in_this: Vec<&'a str>,
+    only_in_other: Vec<&'a str>,
+    changed: Vec<TaskDiff<'a>>
+}
+
+#[derive(Serialize)]
+struct TaskDiff<'a>
+{
+    name: &'a str,
+    changes: Vec<doq::FieldChange>
+}
+
+fn diff_schedules<'a>(this: &'a Schedule, other: &'a Schedule) -> ScheduleDiff<'a>
+{
+    let mut diff = ScheduleDiff { only_in_this: Vec::new(), only_in_other: Vec::new(), changed: Vec::new() };
+    let mut paired = vec![false; other.tasks.len()];
+
+    for task in &this.tasks
+    {
+        let counterpart = other.tasks.iter().position(|t| t.id == task.id)
+            .or_else(|| other.tasks.iter().enumerate().position(|(i, t)| !paired[i] && t.name == task.name));
+
+        match counterpart
+        {
+            Some(index) =>
+            {
+                paired[index] = true;
+                let changes = doq::task_changes(task, &other.tasks[index]);
+                if !changes.is_empty()
+                {
+                    diff.changed.push(TaskDiff { name: &task.name, changes });
+                }
+            },
+            None => diff.only_in_this.push(&task.name)
+        }
+    }
+
+    diff.only_in_other = other.tasks.iter().zip(paired)
+        .filter(|&(_, paired)| !paired)
+        .map(|(task, _)| task.name.as_str())
+        .collect();
+
+    diff
+}
+
+fn print_diff(diff: &ScheduleDiff, this_path: &Path, other_path: &Path)
+{
+    if diff.only_in_this.is_empty() && diff.only_in_other.is_empty() && diff.changed.is_empty()
+    {
+        println!("No differences");
+        return;
+    }
+
+    println!("--- {}", this_path.display());
+    println!("+++ {}", other_path.display());
+
+    for name in &diff.only_in_this
+    {
+        println!("- {}", name);
+    }
+
+    for name in &diff.only_in_other
+    {
+        println!("+ {}", name);
+    }
+
+    for task in &diff.changed
+    {
+        println!("~ {}", task.name);
+        for change in &task.changes
+        {
+            println!("    {}: {} -> {}", change.field, change.before, change.after);
+        }
+    }
+}
+
+
+fn write_csv(out: &mut dyn std::io::Write, tasks: &[(i64, &Task)]) -> std::io::Result<()>
+{
+    fn escape(field: &str) -> String
+    {
+        if field.contains(&[',', '"', '\n', '\r'][..])
+        {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        }
+        else
+        {
+            field.to_owned()
+        }
+    }
+
+    writeln!(out, "name,repeat,at_least,date_completed,date_due,days_until_due")?;
+
+    for &(delta, task) in tasks
+    {
+        let date_completed = match task.date_completed
+        {
+            Some(ref date) => date.as_naive().to_string(),
+            None => String::new()
+        };
+        let date_due = task.date_due.as_naive();
+
+        writeln!(out, "{},{},{},{},{},{}", escape(&task.name), task.repeat, task.at_least, date_completed, date_due, delta)?;
+    }
+
+    Ok(())
+}
+
+
+fn write_markdown(out: &mut dyn std::io::Write, tasks: &[(i64, &Task)], today: NaiveDate) -> std::io::Result<()>
+{
+    fn escape(text: &str) -> String
+    {
+        text.replace('\\', "\\\\").replace('|', "\\|")
+    }
+
+    writeln!(out, "| Task | Repeat | Last completed | Due on | Status |")?;
+    writeln!(out, "| --- | --- | --- | --- | --- |")?;
+
+    for &(_, task) in tasks
+    {
+        let [name, repeat, date_completed, date_due, status] = report_cells(task, today);
+
+        writeln!(out, "| {} | {} | {} | {} | {} |", escape(&name), repeat, date_completed, date_due, status)?;
+    }
+
+    Ok(())
+}
+
+
+/// The plain text cells of a task's row in a Markdown or HTML report.
+fn report_cells(task: &Task, today: NaiveDate) -> [String; 5]
+{
+    let repeat = match (task.repeat, task.at_least)
+    {
+        (Repeat::Never, _) => "--".to_owned(),
+        (repeat, true) => format!("at least {}", repeat),
+        (repeat, false) => repeat.to_string()
+    };
+    let date_completed = task.date_completed.map_or("Never".to_owned(), |date| date.as_naive().to_string());
+    let date_due = task.date_due.as_naive();
+
+    let status = match doq::status(date_due, today)
+    {
+        _ if !task.active => "Paused".to_owned(),
+        TaskStatus::Upcoming(1) => "Due tomorrow".to_owned(),
+        TaskStatus::Upcoming(n) => format!("Due in {} days", n),
+        TaskStatus::DueToday => "Due today".to_owned(),
+        TaskStatus::Overdue(1) => "1 day overdue".to_owned(),
+        TaskStatus::Overdue(n) => format!("{} days overdue", n)
+    };
+
+    [task.name.clone(), repeat, date_completed, date_due.to_string(), status]
+}
+
+
+fn write_html(out: &mut dyn std::io::Write, tasks: &[(i64, &Task)], today: NaiveDate, blocked: &std::collections::HashMap<Uuid, &str>) -> std::io::Result<()>
+{
+    fn escape(text: &str) -> String
+    {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html>")?;
+    writeln!(out, "<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>doq - {}</title>", today)?;
+    writeln!(out, "<style>")?;
+    writeln!(out, "body {{ font-family: sans-serif; margin: 2em; }}")?;
+    writeln!(out, "table {{ border-collapse: collapse; }}")?;
+    writeln!(out, "th, td {{ padding: 0.4em 0.8em; text-align: left; border-bottom: 1px solid #ccc; }}")?;
+    writeln!(out, "tr.overdue {{ background: #f8d0d0; }}")?;
+    writeln!(out, "tr.due-today {{ background: #f8f0c0; }}")?;
+    writeln!(out, "tr.upcoming {{ background: #d8f0d0; }}")?;
+    writeln!(out, "tr.paused {{ color: #888; }}")?;
+    writeln!(out, "</style>")?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body>")?;
+    writeln!(out, "<h1>Generated {}</h1>", today)?;
+    writeln!(out, "<p>{}</p>", summary(tasks, today, blocked))?;
+    writeln!(out, "<table>")?;
+    writeln!(out, "<tr><th>Task</th><th>Repeat</th><th>Last completed</th><th>Due on</th><th>Status</th></tr>")?;
+
+    for &(_, task) in tasks
+    {
+        let class = match doq::status(task.date_due.as_naive(), today)
+        {
+            _ if !task.active => "paused",
+            TaskStatus::Overdue(_) => "overdue",
+            TaskStatus::DueToday => "due-today",
+            TaskStatus::Upcoming(_) => "upcoming"
+        };
+
+        write!(out, "<tr class=\"{}\">", class)?;
+        for cell in &report_cells(task, today)
+        {
+            write!(out, "<td>{}</td>", escape(cell))?;
+        }
+        writeln!(out, "</tr>")?;
+    }
+
+    writeln!(out, "</table>")?;
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")
+}
+
+
+fn write_ics(out: &mut dyn std::io::Write, tasks: &[(i64, &Task)], today: NaiveDate) -> std::io::Result<()>
+{
+    fn escape(text: &str) -> String
+    {
+        text.replace('\\', "\\\\")
+            .replace(';', "\\;")
+            .replace(',', "\\,")
+            .replace('\n', "\\n")
+    }
+
+    // Lines longer than 75 octets must be folded onto continuation lines
+    fn write_line(out: &mut dyn std::io::Write, line: &str) -> std::io::Result<()>
+    {
+        let mut start = 0;
+        let mut limit = 75;
+
+        for (index, c) in line.char_indices()
+        {
+            if index + c.len_utf8() - start > limit
+            {
+                write!(out, "{}\r\n ", &line[start..index])?;
+                start = index;
+                limit = 74;
+            }
+        }
+
+        write!(out, "{}\r\n", &line[start..])
+    }
+
+    let date_format = "%Y%m%d";
+
+    write_line(out, "BEGIN:VCALENDAR")?;
+    write_line(out, "VERSION:2.0")?;
+    write_line(out, &format!("PRODID:-//doq//doq {}//EN", env!("CARGO_PKG_VERSION")))?;
+
+    for &(_, task) in tasks
+    {
+        let date_due = task.date_due.as_naive();
+
+        write_line(out, "BEGIN:VEVENT")?;
+        write_line(out, &format!("UID:{}@doq", task.id))?;
+        write_line(out, &format!("DTSTAMP:{}T000000Z", today.format(date_format)))?;
+        write_line(out, &format!("DTSTART;VALUE=DATE:{}", date_due.format(date_format)))?;
+        write_line(out, &format!("DTEND;VALUE=DATE:{}", (date_due + Duration::days(1)).format(date_format)))?;
+        write_line(out, &format!("SUMMARY:{}", escape(&task.name)))?;
+
+        // An interval of zero never repeats, and isn't valid iCalendar
+        let every = |frequency: &str, interval: u32| match interval
+        {
+            0 => None,
+            _ => Some(format!("FREQ={};INTERVAL={}", frequency, interval))
+        };
+
+        let rule = match task.repeat
+        {
+            Repeat::Never => None,
+            Repeat::Days(days) if days % 7 == 0 => every("WEEKLY", days / 7),
+            Repeat::Days(days) => every("DAILY", days),
+            Repeat::BusinessDays(1) => Some("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR".to_owned()),
+            // Longer business day intervals don't map onto a single rule
+            Repeat::BusinessDays(_) => None,
+            Repeat::Weeks(weeks) => every("WEEKLY", weeks),
+            Repeat::Months(months) => every("MONTHLY", months),
+            Repeat::Years(years) => every("YEARLY", years),
+            Repeat::Compound { years, months, weeks: 0, days: 0 } => every("MONTHLY", years.saturating_mul(12).saturating_add(months)),
+            Repeat::Compound { years: 0, months: 0, weeks, days } => every("DAILY", weeks.saturating_mul(7).saturating_add(days)),
+            // iCalendar can't express a mix of months and days in a single rule
+            Repeat::Compound { .. } => None,
+            Repeat::NthWeekday { n, weekday } =>
+            {
+                let day = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"][weekday.num_days_from_monday() as usize];
+                // doq uses the last one in months without a 5th
+                let n = if n == 5 { -1 } else { n as i32 };
+                Some(format!("FREQ=MONTHLY;BYDAY={}{}", n, day))
+            },
+            Repeat::MonthlyOnDay(day) if day <= 28 => Some(format!("FREQ=MONTHLY;BYMONTHDAY={}", day)),
+            // The last of the candidate days that exists, so short months use their last day
+            Repeat::MonthlyOnDay(day) =>
+            {
+                let days: Vec<_> = (28..=day).map(|day| day.to_string()).collect();
+                Some(format!("FREQ=MONTHLY;BYMONTHDAY={};BYSETPOS=-1", days.join(",")))
             }
+        };
+
+        if let Some(rule) = rule
+        {
+            write_line(out, &format!("RRULE:{}", rule))?;
+        }
+
+        write_line(out, "END:VEVENT")?;
+    }
+
+    write_line(out, "END:VCALENDAR")
+}
+
+
+/// Prints the names of the tasks matching `prefix`, for shell completion.
+/// This runs on every Tab press, so a missing or unreadable schedule prints
+/// nothing rather than being created or reported.
+fn complete_task_names(path: &Path, prefix: &str)
+{
+    // Asking for a passphrase, or reading stdin, would hang the shell
+    if is_stdio(path) || doq::encryption::is_encrypted_file(path)
+    {
+        return;
+    }
+
+    let contents = std::fs::read_to_string(path).ok();
+    let schedule: VersionedSchedule = match contents.and_then(|contents| doq::deserialize(&contents, FileFormat::from_path(path)).ok())
+    {
+        Some(schedule) => schedule,
+        None => return
+    };
+
+    let prefix = doq::fold_name(prefix);
+    for task in schedule.tasks.into_iter().filter_map(VersionedTask::upversioned)
+    {
+        if prefix.is_empty() || close_enough::close_enough(Some(doq::fold_name(&task.name)), &prefix).is_some()
+        {
+            println!("{}", task.name);
+        }
+    }
+}
+
+
+/// Fuzzily matches `name` against the task names, ignoring case and accents,
+/// returning the index of the task. An exact match, looked up in `name_index`,
+/// is preferred. If several tasks match but none exactly, asks which was
+/// meant, or picks the closest if `yes` is set. Where names are duplicated the
+/// first task wins.
+fn match_task(tasks: &[Task], name_index: &std::collections::HashMap<&str, usize>, name: &str, yes: bool) -> usize
+{
+    if let Some(&position) = name_index.get(name)
+    {
+        return position;
+    }
+
+    let folded_name = doq::fold_name(name);
+    if let Some(position) = tasks.iter().position(|t| doq::fold_name(&t.name) == folded_name)
+    {
+        return position;
+    }
+
+    let mut candidates: Vec<&str> = tasks.iter()
+        .map(|t| t.name.as_str())
+        .filter(|task_name| close_enough::close_enough(Some(doq::fold_name(task_name)), &folded_name).is_some())
+        .collect();
+    candidates.sort_by_key(|task_name| task_name.len());
+
+    let task_name = match candidates.len()
+    {
+        0 => fail("No task matching that name"),
+        1 => candidates[0],
+        _ if yes => candidates[0],
+        _ => choose(&format!("Several tasks match '{}':", name), &candidates)
+    };
+
+    name_index[task_name]
+}
+
+fn choose<'a>(prompt: &str, options: &[&'a str]) -> &'a str
+{
+    ensure_stdin_available();
+
+    println!("{}", prompt);
+    for (index, option) in options.iter().enumerate()
+    {
+        println!("    {}) {}", index + 1, option);
+    }
+    println!("Which did you mean? (1-{}) ", options.len());
+
+    let mut buffer = String::new();
+    std::io::stdin().read_line(&mut buffer).or_fail("Failed to read from stdin");
+
+    buffer.trim().parse::<usize>().ok()
+        .and_then(|choice| choice.checked_sub(1))
+        .and_then(|index| options.get(index))
+        .or_fail("No task chosen")
+}
+
+/// Finds the index of the task a subcommand refers to, either exactly by
+/// `--id` or by fuzzily matching the name given in `name_arg`.
+fn target_task(tasks: &[Task], name_index: &std::collections::HashMap<&str, usize>, matches: &clap::ArgMatches, name_arg: &str) -> usize
+{
+    match matches.value_of("id")
+    {
+        Some(id) =>
+        {
+            let id = Uuid::parse_str(id).or_fail("Invalid task id");
+            tasks.iter().position(|t| t.id == id).or_fail("No task with that id")
+        },
+        None =>
+        {
+            match_task(tasks, name_index, matches.value_of(name_arg).unwrap(), matches.is_present("yes"))
+        }
+    }
+}
 
-            let date_due = parse_date_or_today(matches.value_of("on"));
+/// The arguments `list` and `export --markdown` use to pick which tasks to show.
+fn filter_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>>
+{
+    use clap::Arg;
+
+    vec![
+        Arg::with_name("overdue")
+            .help("Show overdue tasks")
+            .long("overdue"),
+        Arg::with_name("due_today")
+            .help("Show tasks due today")
+            .long("due-today"),
+        Arg::with_name("due_within")
+            .help("Only show tasks due within this many days, including overdue tasks")
+            .long("due-within")
+            .takes_value(true)
+            .validator(validate_days),
+        Arg::with_name("tag")
+            .help("Only show tasks with this tag")
+            .long("tag")
+            .takes_value(true),
+        Arg::with_name("project")
+            .help("Only show tasks in this project")
+            .long("project")
+            .takes_value(true)
+    ]
+}
 
-            schedule.tasks.push(
-                Task
-                {
-                    name: name.to_owned(),
-                    repeat,
-                    date_completed: None,
-                    date_due: date_due.into(),
-                    at_least
-                });
+fn task_id_arg<'a, 'b>() -> clap::Arg<'a, 'b>
+{
+    clap::Arg::with_name("id")
+        .help("Refer to the task by its id instead of its name")
+        .long("id")
+        .takes_value(true)
+        .validator(validate_id)
+}
 
-            write_file(dotfile, &schedule);
-        },
+/// Asks for a value on stdin, repeating the question until `parse` accepts
+/// the (trimmed) answer.
+fn ask<T, F>(prompt: &str, parse: F) -> T
+where
+    F: Fn(&str) -> Result<T, String>
+{
+    use std::io::Write;
 
-        ("edit", Some(matches)) =>
+    ensure_stdin_available();
+
+    loop
+    {
+        print!("{}: ", prompt);
+        let _ = std::io::stdout().flush();
+
+        let mut buffer = String::new();
+        if std::io::stdin().read_line(&mut buffer).or_fail("Failed to read from stdin") == 0
         {
-            {
-                let name = matches.value_of("name").unwrap();
+            fail("No answer given");
+        }
+
+        match parse(buffer.trim())
+        {
+            Ok(value) => return value,
+            Err(e) => eprintln!("{}", e)
+        }
+    }
+}
 
-                // Remove DRY-fail with did command
-                let task_name = close_enough::close_enough(schedule.tasks.iter().map(|t| &t.name), name).or_fail("No task matching that name").to_owned();
+fn confirm(prompt: &str) -> bool
+{
+    ensure_stdin_available();
 
-                let task = schedule.tasks.iter_mut().find(|t| t.name == task_name).unwrap();
+    println!("{} (y/N) ", prompt);
+    let mut buffer = String::new();
+    std::io::stdin().read_line(&mut buffer).or_fail("Failed to read from stdin");
 
-                if let Some(new_name) = matches.value_of("rename")
-                {
-                    task.name = new_name.to_owned();
-                }
+    let command = buffer.trim().to_lowercase();
+    command == "y" || command == "yes"
+}
 
-                if let Some(on) = matches.value_of("on")
-                {
-                    task.date_due = parse_date(on).into();
-                }
 
-                if let Some(repeat) = matches.value_of("repeat")
-                {
-                    task.repeat = doq::repeat_from_string(repeat).unwrap_or_else(|e| fail(e));
-                }
+/// Set once the schedule has been read from stdin, after which there's
+/// nothing left there to answer prompts with.
+static STDIN_CONSUMED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
 
-                if let Some(at_least) = matches.value_of("at_least")
+fn ensure_stdin_available()
+{
+    if STDIN_CONSUMED.load(std::sync::atomic::Ordering::Relaxed)
+    {
+        fail("Can't prompt while reading the schedule from stdin. Pass --yes, or --id to pick a task");
+    }
+}
+
+/// Prints a status message, on stderr if stdout is taken by the schedule.
+fn status(dotfile: &Path, message: &str)
+{
+    match is_stdio(dotfile)
+    {
+        true => eprintln!("{}", message),
+        false => println!("{}", message)
+    }
+}
+
+
+fn expand_home(path: &Path, home: &Path) -> PathBuf
+{
+    match path.strip_prefix("~")
+    {
+        Ok(rest) => home.join(rest),
+        Err(_) => path.to_owned()
+    }
+}
+
+/// Upgrades every task in the schedule to the current format, reporting the
+/// ones saved by older versions. Nothing is written if any can't be upgraded.
+fn migrate(dotfile: &Path, save: SaveOptions)
+{
+    let schedule: VersionedSchedule = read_file(dotfile, "schedule");
+
+    let mut upgraded = 0;
+    let mut failed = 0;
+    let mut upversion = |tasks: Vec<VersionedTask>| -> Vec<Task>
+    {
+        tasks.into_iter().filter_map(|task|
+        {
+            let name = task.name().to_owned();
+            let is_old = match task
+            {
+                VersionedTask::Version010(_) => true,
+                VersionedTask::Current(_) => false
+            };
+
+            match task.upversioned()
+            {
+                Some(task) =>
                 {
-                    task.at_least = at_least.parse().unwrap();
+                    if is_old
+                    {
+                        status(dotfile, &format!("Upgraded '{}' from the 0.1.0 format", name));
+                        upgraded += 1;
+                    }
+                    Some(task)
+                },
+                None =>
+                {
+                    warn(&format!("Couldn't upgrade '{}'", name));
+                    failed += 1;
+                    None
                 }
             }
+        }).collect()
+    };
 
-            write_file(dotfile, &schedule);
-        }
+    let schedule = Schedule { tasks: upversion(schedule.tasks), archived: upversion(schedule.archived) };
 
-        ("remove", Some(matches)) =>
+    if upgraded == 0 && failed == 0
+    {
+        status(dotfile, "No tasks needed upgrading");
+    }
+
+    if failed > 0
+    {
+        fail(&format!("{} task{} couldn't be upgraded, so {} was left as it is. Fix or remove them by hand and migrate again",
+            failed, if failed == 1 { "" } else { "s" }, dotfile.display()));
+    }
+
+    // The upgraded tasks are already listed, which is all a dry run needs to show
+    match save.dry_run
+    {
+        true => status(dotfile, &format!("Dry run, so {} was left as it is", dotfile.display())),
+        false => save_schedule(dotfile, &schedule, save)
+    }
+}
+
+/// True if `path` is `-`, meaning read from stdin and write to stdout.
+fn is_stdio(path: &Path) -> bool
+{
+    path == Path::new("-")
+}
+
+fn ensure_file_exists(path: &Path, default_content: &Schedule, encrypt: bool)
+{
+    if !is_stdio(path) && !path.exists()
+    {
+        if let Some(parent) = path.parent()
         {
-            let name = matches.value_of("name").unwrap();
-            let index = schedule.tasks.iter().position(|t| t.name == name).or_fail("No task with that name");
-            schedule.tasks.swap_remove(index);
-            write_file(dotfile, &schedule);
+            std::fs::create_dir_all(parent).or_fail("Failed to create directory");
         }
 
-        ("did", Some(matches)) =>
-        {
-            let name = matches.value_of("task").unwrap();
+        write_file(path, default_content, encrypt);
+    }
+}
 
-            let date = parse_date_or_today(matches.value_of("on"));
-            let yes = matches.is_present("yes");
+/// Reads a file in the format given by its extension, or stdin for `-`.
+/// `what` names the file in parse errors.
+fn read_file<T>(path: &Path, what: &str) -> T
+where for <'de>
+    T: Deserialize<'de>
+{
+    let contents = read_to_string(path).or_fail("Failed to read file");
+    doq::deserialize(&contents, FileFormat::from_path(path)).unwrap_or_else(|e| fail(&parse_error(what, e)))
+}
 
-            let task_name = close_enough::close_enough(schedule.tasks.iter().map(|t| &t.name), name).or_fail("No task matching that name").to_owned();
+/// Reads the config file, suggesting removing it if it can't be parsed.
+fn read_config(path: &Path) -> AppConfig
+{
+    let what = format!("config {}", path.display());
+    let contents = std::fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("Failed to read {}: {}", what, e)));
 
-            let (should_write, should_delete) = {
-                let task = schedule.tasks.iter_mut().find(|t| t.name == task_name).unwrap();
+    doq::deserialize(&contents, FileFormat::from_path(path)).unwrap_or_else(|e|
+    {
+        fail(&format!("{}\nDelete it to go back to the default settings.", parse_error(&what, e)))
+    })
+}
 
-                let proceed = match yes
-                {
-                    true => true,
-                    false =>
-                    {
-                        println!("Mark task '{}' as done on {}? (y/N) ", task.name, date);
-                        let mut buffer = String::new();
-                        std::io::stdin().read_line(&mut buffer).or_fail("Failed to read from stdin");
+/// Describes a parse error, including where in the file it happened.
+fn parse_error(what: &str, error: doq::Error) -> String
+{
+    match error
+    {
+        doq::Error::Parse(reason, Some((line, column))) => format!("Failed to parse {} at line {}, column {}: {}", what, line, column, reason),
+        doq::Error::Parse(reason, None) => format!("Failed to parse {}: {}", what, reason),
+        e => e.to_string()
+    }
+}
 
-                        let command = buffer.trim().to_lowercase();
-                        (command == "y" || command == "yes")
-                    }
-                };
+/// Reads a file, or stdin for `-`, decrypting it if it's encrypted.
+fn read_to_string(path: &Path) -> std::io::Result<String>
+{
+    use std::io::{ Error, ErrorKind };
 
-                if proceed
-                {
-                    let date_completed = date;
-                    let previous_date_due = task.date_due.as_naive().or_fail("Failed to parse date");
-                    let repeat_start = if task.at_least { date_completed } else { previous_date_due };
-                    let next_due_date = doq::next_due_date(repeat_start, date_completed, task.repeat);
-                    let should_delete = match next_due_date
-                    {
-                        Some(next_due_date) => {
-                            task.date_completed = Some(date_completed.into());
-                            task.date_due = next_due_date.into();
-                            false
-                        },
-                        None => true
-                    };
+    let contents = match is_stdio(path)
+    {
+        true =>
+        {
+            use std::io::Read;
 
-                    (true, should_delete)
-                }
-                else
-                {
-                    eprintln!("Cancelling");
-                    (false, false)
-                }
-            };
+            STDIN_CONSUMED.store(true, std::sync::atomic::Ordering::Relaxed);
+
+            let mut contents = Vec::new();
+            std::io::stdin().read_to_end(&mut contents).map(|_| contents)
+        },
+        false => std::fs::read(path)
+    }?;
 
-            if should_delete
+    match doq::encryption::is_encrypted(&contents)
+    {
+        true =>
+        {
+            if is_stdio(path)
             {
-                // TODO: Remove DRY fail with remove command.
-                let index = schedule.tasks.iter().position(|t| t.name == task_name).unwrap();
-                schedule.tasks.swap_remove(index);
+                STDIN_ENCRYPTED.store(true, std::sync::atomic::Ordering::Relaxed);
             }
+            decrypt(path, &contents).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+        },
+        false => String::from_utf8(contents).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+/// Set once an encrypted schedule has been read from stdin, so that it's
+/// written back to stdout encrypted too.
+static STDIN_ENCRYPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(not(feature = "encryption"))]
+const NO_ENCRYPTION: doq::Error = doq::Error::Encryption("The file is encrypted, but doq was built without the encryption feature");
 
-            if should_write
+/// The passphrase for encrypted schedules, from DOQ_PASSPHRASE or asked for
+/// once. With `confirm` it's asked for twice, to catch typos when a file is
+/// first encrypted.
+#[cfg(feature = "encryption")]
+fn passphrase(path: &Path, confirm: bool) -> &'static str
+{
+    static PASSPHRASE: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+    PASSPHRASE.get_or_init(||
+    {
+        if let Ok(passphrase) = std::env::var("DOQ_PASSPHRASE")
+        {
+            return passphrase;
+        }
+
+        let ask = |prompt: &str| rpassword::prompt_password(prompt).or_fail("Failed to read the passphrase. Set DOQ_PASSPHRASE to give it without a prompt");
+        let passphrase = ask(&format!("Passphrase for {}: ", path.display()));
+        if confirm
+        {
+            if passphrase.is_empty()
+            {
+                fail("The passphrase can't be empty");
+            }
+            if ask("Repeat the passphrase: ") != passphrase
             {
-                write_file(dotfile, &schedule);
+                fail("The passphrases don't match");
             }
+        }
+        passphrase
+    })
+}
+
+#[cfg(feature = "encryption")]
+fn decrypt(path: &Path, contents: &[u8]) -> Result<String, doq::Error>
+{
+    doq::encryption::decrypt(contents, passphrase(path, false))
+}
+
+#[cfg(not(feature = "encryption"))]
+fn decrypt(_path: &Path, _contents: &[u8]) -> Result<String, doq::Error>
+{
+    Err(NO_ENCRYPTION)
+}
+
+#[cfg(feature = "encryption")]
+fn write_encrypted(path: &Path, schedule: &Schedule) -> Result<(), doq::Error>
+{
+    match is_stdio(path)
+    {
+        true =>
+        {
+            use std::io::Write;
+
+            let passphrase = passphrase(path, !STDIN_ENCRYPTED.load(std::sync::atomic::Ordering::Relaxed));
+            let contents = doq::encryption::encrypt(&doq::serialize(schedule, FileFormat::from_path(path))?, passphrase)?;
+            std::io::stdout().write_all(&contents).map_err(|e| doq::Error::Io(e.to_string()))
         },
-        _ => ()
+        false => doq::encryption::write_file(path, schedule, passphrase(path, !doq::encryption::is_encrypted_file(path)))
     }
+}
 
+#[cfg(not(feature = "encryption"))]
+fn write_encrypted(_path: &Path, _schedule: &Schedule) -> Result<(), doq::Error>
+{
+    Err(NO_ENCRYPTION)
+}
+
+/// Reads and parses the schedule, decrypting it if need be.
+fn try_load_schedule(path: &Path) -> Result<Schedule, doq::Error>
+{
+    read_to_string(path)
+        .map_err(|e| doq::Error::Io(e.to_string()))
+        .and_then(|contents| Schedule::parse(&contents, FileFormat::from_path(path)))
+}
+
+fn load_schedule(path: &Path) -> Schedule
+{
+    let what = match is_stdio(path)
+    {
+        true => "schedule".to_owned(),
+        false => format!("schedule {}", path.display())
+    };
+
+    try_load_schedule(path).unwrap_or_else(|e| match e
     {
-        // TODO: Stretch column sizes to fit max item
-        println!("{: <20} {: >4}  {: <33} {: <33}", "Task", "", "Last completed", "Due on");
-        println!("{: <20} {: >4}  {: <33} {: <33}", "===", "", "===", "===");
+        doq::Error::Io(reason) => fail(&format!("Failed to read {}: {}", what, reason)),
+        e @ doq::Error::Parse(..) => fail(&parse_error(&what, e)),
+        e => fail(&e.to_string())
+    })
+}
 
-        let mut delta_tasks: Vec<_> = schedule.tasks.iter().map(
-            |task|
-            {
-                let date_due = task.date_due.as_naive().or_fail("Failed to parse date");
-                let today = Utc::today().naive_utc();
-                let delta = doq::days_until_due(date_due, today);
-                (delta, task)
-            }).collect();
-        delta_tasks.sort_by_key(|&(delta, _)| delta);
+#[derive(Clone, Copy)]
+struct SaveOptions<'a>
+{
+    /// Only say what would have been saved.
+    dry_run: bool,
+
+    /// Encrypt the file, even if it wasn't already.
+    encrypt: bool,
+
+    /// Commit the file to git after saving, with this message.
+    commit_message: Option<&'a str>
+}
+
+/// Writes the schedule, unless this is a dry run, in which case the tasks it
+/// would have changed are printed instead.
+fn save_schedule(path: &Path, schedule: &Schedule, save: SaveOptions)
+{
+    if save.dry_run
+    {
+        print_dry_run(path, schedule);
+        return;
+    }
 
-        let red = Color::Fixed(9);
-        let green = Color::Fixed(10);
-        let yellow = Color::Fixed(11);
-        let white = Color::Fixed(15);
+    write_file(path, schedule, save.encrypt);
 
-        for &(delta, task) in &delta_tasks
+    if let Some(message) = save.commit_message
+    {
+        if !is_stdio(path)
         {
-            let leader = if task.at_least { '<' } else { ' ' };
+            git_commit(path, message);
+        }
+    }
+}
 
-            let freq_string = match task.repeat
-            {
-                Repeat::Days(days) => format!("{}{}d", leader, days),
-                Repeat::Months(months) => format!("{}{}m", leader, months),
-                Repeat::Years(years) => format!("{}{}y", leader, years),
-                Repeat::Never => "--".to_owned()
-            };
+/// Commits `path` to git, if it's in a repository and has changed. The
+/// schedule is already saved, so failures are only warned about.
+fn git_commit(path: &Path, message: &str)
+{
+    use std::process::Command;
 
-            let (datestring, days_ago_text) = match &task.date_completed
-            {
-                &Some(ref date) =>
-                {
-                    let date = date.as_naive().or_fail("Failed to parse date");
-                    let days = Utc::today().naive_utc().signed_duration_since(date).num_days();
-                    let days_ago_text = match days
-                    {
-                        0 => "    Today".to_owned(),
-                        1 => "  1 day ago".to_owned(),
-                        n => format!("{: >3} days ago", n)
-                    };
+    let dir = match path.parent()
+    {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new(".")
+    };
+    let file = path.file_name().unwrap();
+    let git = |args: &[&str]|
+    {
+        let mut command = Command::new("git");
+        command.current_dir(dir).args(args);
+        command
+    };
 
-                    (date.to_string(), days_ago_text)
-                },
-                &None => ("Never".to_owned(), "".to_owned())
-            };
+    let in_repository = git(&["rev-parse", "--is-inside-work-tree"]).output().is_ok_and(|output| output.status.success());
+    if !in_repository
+    {
+        return;
+    }
 
-            let due_date_string = task.date_due.as_naive().or_fail("Failed to parse date").to_string();
+    let changed = git(&["status", "--porcelain", "--"]).arg(file).output().map(|output| !output.stdout.is_empty());
+    let result = match changed
+    {
+        Ok(false) => return,
+        Ok(true) => git(&["add", "--"]).arg(file).output().and_then(|output| match output.status.success()
+        {
+            true => git(&["commit", "-q", "-m", message, "--"]).arg(file).output(),
+            false => Ok(output)
+        }),
+        Err(e) => Err(e)
+    };
 
-            let (color, status) = match delta
-            {
-                1 => (white, "(Due tomorrow)".to_owned()),
-                0 => (yellow, "(Due today)".to_owned()),
-                -1 => (red, "(1 day overdue!)".to_owned()),
-                n if n > 0 => (green, format!("(Due in {} days)", n)),
-                n => (red, format!("({} days overdue!)", -n))
-            };
+    match result
+    {
+        Ok(ref output) if output.status.success() => (),
+        Ok(output) => warn(&format!("Couldn't commit {} to git: {}", path.display(), String::from_utf8_lossy(&output.stderr).trim())),
+        Err(e) => warn(&format!("Couldn't run git: {}", e))
+    }
+}
 
-            let line = format!("{: <20} {: >4}  {: <16} {: <16} {: <16} {: <16}", task.name, freq_string, datestring, days_ago_text, due_date_string, status);
+/// Prints each task that saving `schedule` to `path` would add, change or
+/// remove, as it would be saved.
+fn print_dry_run(path: &Path, schedule: &Schedule)
+{
+    // Stdin has already been read, so there's nothing there to compare with
+    let saved = match is_stdio(path) || !path.exists()
+    {
+        true => Schedule::default(),
+        false => load_schedule(path)
+    };
+
+    let as_yaml = |task: &Task| doq::serialize(task, FileFormat::Yaml).or_fail("Failed to serialize task");
+    let saved_tasks: std::collections::HashMap<Uuid, String> = saved.tasks.iter().chain(&saved.archived)
+        .map(|task| (task.id, as_yaml(task)))
+        .collect();
 
-            println!("{}", color.paint(line));
+    status(path, &format!("Dry run, so {} wasn't changed", path.display()));
+
+    let tasks = schedule.tasks.iter().map(|task| (task, "would be")).chain(schedule.archived.iter().map(|task| (task, "would be archived as")));
+    for (task, change) in tasks
+    {
+        let yaml = as_yaml(task);
+        if saved_tasks.get(&task.id) != Some(&yaml)
+        {
+            status(path, &format!("'{}' {}:\n{}", task.name, change, yaml.trim_start_matches("---\n").trim_end()));
         }
     }
+
+    // Tasks from old files get new ids each time they're loaded, so their names are checked too
+    let kept = |saved: &Task| schedule.tasks.iter().chain(&schedule.archived).any(|task| task.id == saved.id || task.name == saved.name);
+    for task in saved.tasks.iter().chain(&saved.archived).filter(|task| !kept(task))
+    {
+        status(path, &format!("'{}' would be removed", task.name));
+    }
+}
+
+/// Writes the schedule, encrypted if `encrypt` is set or it was already.
+fn write_file(path: &Path, schedule: &Schedule, encrypt: bool)
+{
+    let encrypted = match is_stdio(path)
+    {
+        true => STDIN_ENCRYPTED.load(std::sync::atomic::Ordering::Relaxed),
+        false => doq::encryption::is_encrypted_file(path)
+    };
+
+    let result = match is_stdio(path)
+    {
+        _ if encrypt || encrypted => write_encrypted(path, schedule),
+        true =>
+        {
+            use std::io::Write;
+
+            let stdout = std::io::stdout();
+            let mut stdout = stdout.lock();
+            doq::serialize(schedule, FileFormat::from_path(path))
+                .and_then(|contents| stdout.write_all(contents.as_bytes()).map_err(|e| doq::Error::Io(e.to_string())))
+        },
+        false => schedule.save(path)
+    };
+
+    result.or_fail("Failed to write to file");
+}
+
+fn validate_repeat(value: String) -> Result<(), String>
+{
+    doq::repeat_from_string(&value).map(|_| ()).map_err(|e| e.to_string())
 }
 
 
-fn ensure_file_exists<T: Serialize>(path: &Path, default_content: &T)
+fn validate_offset(value: String) -> Result<(), String>
+{
+    let today = Local::today().naive_local();
+    doq::offset_from_string(&value, today).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn validate_days(value: String) -> Result<(), String>
 {
-    use std::fs::File;
+    value.parse::<u32>().map(|_| ()).map_err(|_| "Expected a number of days".to_owned())
+}
 
-    if !path.exists()
+fn validate_name(value: String) -> Result<(), String>
+{
+    match value.trim().is_empty()
     {
-        let file = &mut File::create(path).or_fail("Failed to create file");
-        serde_yaml::to_writer(file, default_content).or_fail("Failed to write to file");
+        true => Err("Task names can't be empty".to_owned()),
+        false => Ok(())
     }
 }
 
-fn read_file<T>(path: &Path) -> T
-where for <'de>
-    T: Deserialize<'de>
+fn validate_id(value: String) -> Result<(), String>
 {
-    use std::fs::File;
+    Uuid::parse_str(&value).map(|_| ()).map_err(|_| "Expected a task id".to_owned())
+}
+
+fn validate_month(value: String) -> Result<(), String>
+{
+    parse_month(&value).map(|_| ()).ok_or_else(|| "Expected a month as YYYY-MM".to_owned())
+}
+
+fn validate_priority(value: String) -> Result<(), String>
+{
+    value.parse::<u8>().map(|_| ()).map_err(|_| "Expected a number from 0 to 255".to_owned())
+}
+
+fn validate_date(value: String) -> Result<(), String>
+{
+    let today = Local::today().naive_local();
+    doq::date_from_string(&value, today).map(|_| ()).map_err(|e| format!("Invalid date '{}'. {}", value, e))
+}
 
-    let file = &File::open(path).or_fail("Failed to read file");
-    serde_yaml::from_reader(file).or_fail("Failed to parse file")
+fn validate_seconds(value: String) -> Result<(), String>
+{
+    match value.parse::<u64>()
+    {
+        Ok(0) | Err(_) => Err("Expected a number of seconds greater than zero".to_owned()),
+        Ok(_) => Ok(())
+    }
 }
 
-fn write_file<T: Serialize>(path: &Path, data: &T)
+fn validate_times(value: String) -> Result<(), String>
 {
-    use std::fs::File;
+    match value.parse::<u32>()
+    {
+        Ok(0) | Err(_) => Err("Expected a number of times greater than zero".to_owned()),
+        Ok(_) => Ok(())
+    }
+}
 
-    let file = &mut File::create(path).or_fail("Failed to open file");
-    serde_yaml::to_writer(file, data).or_fail("Failed to write to file");
+fn validate_until(value: String) -> Result<(), String>
+{
+    match value.as_str()
+    {
+        "never" => Ok(()),
+        _ => validate_date(value)
+    }
 }
 
 
-fn parse_date(date: &str) -> NaiveDate
+/// Parses a `YYYY-MM` string into the first day of that month.
+fn parse_month(month: &str) -> Option<NaiveDate>
+{
+    NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok()
+}
+
+fn parse_date(date: &str, today: NaiveDate) -> NaiveDate
 {
-    use std::str::FromStr;
-    NaiveDate::from_str(date).or_fail("Invalid date format")
+    doq::date_from_string(date, today).unwrap_or_else(|e| fail(&format!("Invalid date '{}'. {}", date, e)))
 }
 
-fn parse_date_or_today(date: Option<&str>) -> NaiveDate
+fn parse_date_or_today(date: Option<&str>, today: NaiveDate) -> NaiveDate
 {
     match date
     {
-        Some(date) => parse_date(date),
-        None => Utc::today().naive_utc()
+        Some(date) => parse_date(date, today),
+        None => today
     }
 }
 
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_truncate()
+    {
+        assert_eq!(truncate("water plants", 20), "water plants");
+        assert_eq!(truncate("water plants", 12), "water plants");
+        assert_eq!(truncate("water plants", 8), "water p…");
+        assert_eq!(truncate("água", 3), "ág…");
+    }
+
+
+    #[test]
+    fn test_fit_columns()
+    {
+        // "water the plants" | 1d | Pri | 2017-05-01 | 3 days ago | 2017-05-02 | (Due in 1 days)
+        let full = [16, 3, 3, 10, 10, 10, 15];
+        let full_width = 16 + 1 + 3 + 1 + 3 + 2 + 21 + 1 + 10 + 1 + 15;
+
+        let mut widths = full;
+        assert!(fit_columns(&mut widths, 100));
+        assert_eq!(widths, full);
+
+        let mut widths = full;
+        assert!(fit_columns(&mut widths, full_width));
+        assert_eq!(widths, full);
+
+        // Dropping "days ago" narrows the completed column from 21 to 10
+        let mut widths = full;
+        assert!(!fit_columns(&mut widths, full_width - 1));
+        assert_eq!(widths, full);
+
+        let mut widths = full;
+        assert!(!fit_columns(&mut widths, full_width - 15));
+        assert_eq!(widths[0], 12);
+
+        let mut widths = full;
+        assert!(!fit_columns(&mut widths, 20));
+        assert_eq!(widths[0], 8);
+
+        let mut widths = [5, 3, 3, 10, 10, 10, 15];
+        fit_columns(&mut widths, 20);
+        assert_eq!(widths[0], 5);
+    }
+}
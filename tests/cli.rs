@@ -0,0 +1,2053 @@
+extern crate serde_json;
+
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{ Command, Output, Stdio };
+
+
+struct Scratch
+{
+    dir: PathBuf
+}
+
+impl Scratch
+{
+    fn new(name: &str) -> Scratch
+    {
+        let dir = std::env::temp_dir().join(format!("doq-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Scratch { dir }
+    }
+
+    fn schedule(&self) -> PathBuf
+    {
+        self.dir.join("schedule.yaml")
+    }
+
+    fn doq(&self, args: &[&str]) -> Output
+    {
+        self.command(args).output().unwrap()
+    }
+
+    fn doq_without_file(&self, args: &[&str]) -> Output
+    {
+        self.command_without_file(args).output().unwrap()
+    }
+
+    fn command(&self, args: &[&str]) -> Command
+    {
+        let schedule = self.schedule();
+        let mut full_args = vec!["--file", schedule.to_str().unwrap()];
+        full_args.extend(args);
+        self.command_without_file(&full_args)
+    }
+
+    fn command_without_file(&self, args: &[&str]) -> Command
+    {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_doq"));
+        command
+            .args(args)
+            .env("HOME", &self.dir)
+            .env("XDG_CONFIG_HOME", self.dir.join(".config"))
+            .env_remove("NO_COLOR")
+            .current_dir(&self.dir)
+            .stdin(Stdio::null());
+        command
+    }
+}
+
+impl Drop for Scratch
+{
+    fn drop(&mut self)
+    {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+
+fn stderr(output: &Output) -> String
+{
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+
+#[test]
+fn invalid_repeat_is_rejected()
+{
+    let scratch = Scratch::new("invalid-repeat");
+    let output = scratch.doq(&["add", "foo", "--repeat", "5x"]);
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Expected a suffix (d, b, w, m, y) for days, business days, weeks, months, or years"));
+    assert!(!scratch.schedule().exists());
+
+    let output = scratch.doq(&["add", "foo", "--repeat", "0d"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Expected a number greater than zero"));
+    assert!(!scratch.schedule().exists());
+}
+
+
+#[test]
+fn task_names_are_trimmed_and_must_not_be_empty()
+{
+    let scratch = Scratch::new("names");
+    let output = scratch.doq(&["add", "   ", "--repeat", "1d"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Task names can't be empty"));
+    assert!(!scratch.schedule().exists());
+
+    assert!(scratch.doq(&["add", "  dishes ", "--repeat", "1d"]).status.success());
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("name: dishes\n"));
+
+    assert!(!scratch.doq(&["edit", "dishes", "--rename", ""]).status.success());
+    assert!(scratch.doq(&["edit", "dishes", "--rename", " washing up  "]).status.success());
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("name: washing up\n"));
+}
+
+
+#[test]
+fn invalid_date_is_rejected()
+{
+    let scratch = Scratch::new("invalid-date");
+    let output = scratch.doq(&["add", "foo", "--repeat", "1d", "--on", "not-a-date"]);
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Invalid date 'not-a-date'"));
+    assert!(!scratch.schedule().exists());
+}
+
+
+#[test]
+fn remove_requires_confirmation()
+{
+    let scratch = Scratch::new("remove-confirm");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+    let before = fs::read(scratch.schedule()).unwrap();
+
+    let output = scratch.doq(&["remove", "dishes"]);
+    assert!(stderr(&output).contains("Cancelling"));
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+
+    assert!(scratch.doq(&["remove", "dishes", "--yes"]).status.success());
+    assert!(!fs::read_to_string(scratch.schedule()).unwrap().contains("dishes"));
+}
+
+
+#[test]
+fn remove_matches_fuzzily()
+{
+    let scratch = Scratch::new("remove-fuzzy");
+    assert!(scratch.doq(&["add", "water plants", "--repeat", "3d"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+
+    let output = scratch.doq(&["remove", "water", "-y"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Removed task 'water plants'"));
+
+    let schedule = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(!schedule.contains("water plants"));
+    assert!(schedule.contains("dishes"));
+}
+
+
+#[test]
+fn matching_ignores_case_and_accents()
+{
+    let scratch = Scratch::new("match-case");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+    assert!(scratch.doq(&["add", "café run", "--repeat", "1w"]).status.success());
+    assert!(scratch.doq(&["add", "Cafe", "--repeat", "1w"]).status.success());
+
+    let output = scratch.doq(&["remove", "DISHES", "-y"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Removed task 'dishes'"));
+
+    let output = scratch.doq(&["remove", "CAFE RUN", "-y"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Removed task 'café run'"));
+
+    let output = scratch.doq(&["remove", "Cafe", "-y"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Removed task 'Cafe'"));
+}
+
+
+#[test]
+fn did_in_the_future_requires_force()
+{
+    let scratch = Scratch::new("did-future");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+    let before = fs::read(scratch.schedule()).unwrap();
+
+    let output = scratch.doq(&["did", "dishes", "--on", "+3d"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("is in the future"));
+    assert!(stderr(&output).contains("Cancelling"));
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+
+    assert!(scratch.doq(&["did", "dishes", "--on", "+3d", "--force"]).status.success());
+    assert_ne!(fs::read(scratch.schedule()).unwrap(), before);
+}
+
+
+#[test]
+fn did_warns_when_done_well_before_due()
+{
+    let scratch = Scratch::new("did-early");
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "+30d"]).status.success());
+    assert!(scratch.doq(&["add", "gym", "--repeat", "7d", "--at-least", "--on", "+30d"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "+2d"]).status.success());
+
+    let output = scratch.doq(&["did", "taxes", "--yes"]);
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("warning: 'taxes' isn't due until"));
+
+    assert!(!stderr(&scratch.doq(&["did", "gym", "--yes"])).contains("warning"));
+    assert!(!stderr(&scratch.doq(&["did", "dishes", "--yes"])).contains("warning"));
+
+    fs::write(scratch.dir.join(".doq_config"), "early_completion_days: 400\n").unwrap();
+    assert!(!stderr(&scratch.doq(&["did", "taxes", "--yes"])).contains("warning"));
+}
+
+
+#[test]
+fn completed_one_off_tasks_are_archived()
+{
+    let scratch = Scratch::new("archive");
+    assert!(scratch.doq(&["add", "party", "--repeat", "never", "--on", "2017-05-01"]).status.success());
+    assert!(scratch.doq(&["did", "party", "--on", "2017-05-02", "--yes"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).into_owned();
+    assert!(!output.contains("party"));
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["archive"]).stdout).into_owned();
+    assert!(output.lines().any(|line| line.starts_with("party") && line.ends_with("2017-05-02")));
+
+    assert!(scratch.doq(&["restore", "party", "--on", "2017-06-01"]).status.success());
+    let output = String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).into_owned();
+    assert!(output.lines().any(|line| line.starts_with("party") && line.contains("2017-06-01")));
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["archive"]).stdout).into_owned();
+    assert!(output.contains("No archived tasks"));
+}
+
+
+#[test]
+fn ambiguous_names_ask_which_task_was_meant()
+{
+    use std::io::Write;
+
+    let scratch = Scratch::new("ambiguous");
+    assert!(scratch.doq(&["add", "water plants", "--repeat", "7d", "--on", "2017-05-01"]).status.success());
+    assert!(scratch.doq(&["add", "watch logs", "--repeat", "1d", "--on", "2017-05-01"]).status.success());
+    let before = fs::read(scratch.schedule()).unwrap();
+
+    let output = scratch.doq(&["skip", "wat"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(!output.status.success());
+    assert!(stdout.contains("1) watch logs") && stdout.contains("2) water plants"));
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+
+    let mut child = scratch.command(&["skip", "wat"]).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(b"2\n").unwrap();
+    assert!(child.wait_with_output().unwrap().status.success());
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("date_due: \"2017-05-08\""));
+
+    assert!(scratch.doq(&["did", "wat", "--on", "2017-05-01", "--yes"]).status.success());
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("date_due: \"2017-05-02\""));
+}
+
+
+#[test]
+fn catch_up_advances_one_missed_occurrence_at_a_time()
+{
+    let scratch = Scratch::new("catch-up");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2017-05-01"]).status.success());
+
+    let output = scratch.doq(&["did", "dishes", "--on", "2017-05-04", "--yes", "--catch-up"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("'dishes' missed 3 occurrences since 2017-05-01. Next due 2017-05-02"));
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("date_due: \"2017-05-02\""));
+
+    assert!(scratch.doq(&["did", "dishes", "--on", "2017-05-04", "--yes"]).status.success());
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("date_due: \"2017-05-05\""));
+}
+
+
+#[test]
+fn log_lists_completions_most_recent_first()
+{
+    let scratch = Scratch::new("log");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2017-05-01"]).status.success());
+    assert!(scratch.doq(&["did", "dishes", "--on", "2017-05-01", "-y"]).status.success());
+    assert!(scratch.doq(&["did", "dishes", "--on", "2017-05-03", "-y"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["log", "dish"]).stdout).into_owned();
+    let first = output.find("2017-05-03").unwrap();
+    let second = output.find("2017-05-01").unwrap();
+    assert!(first < second);
+}
+
+
+#[test]
+fn undo_reverts_did()
+{
+    let scratch = Scratch::new("undo");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "3d", "--on", "2017-05-01"]).status.success());
+    assert!(scratch.doq(&["did", "dishes", "--on", "2017-04-28", "-y"]).status.success());
+    let before = fs::read(scratch.schedule()).unwrap();
+
+    assert!(scratch.doq(&["did", "dishes", "--on", "2017-05-09", "-y"]).status.success());
+    assert_ne!(fs::read(scratch.schedule()).unwrap(), before);
+
+    assert!(scratch.doq(&["undo", "dishes"]).status.success());
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+}
+
+
+#[test]
+fn undo_without_history_reports_nothing_to_undo()
+{
+    let scratch = Scratch::new("undo-empty");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+    let before = fs::read(scratch.schedule()).unwrap();
+
+    let output = scratch.doq(&["undo", "dishes"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Nothing to undo"));
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+}
+
+
+#[test]
+fn snooze_moves_only_the_due_date()
+{
+    let scratch = Scratch::new("snooze");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "7d", "--on", "2017-05-01"]).status.success());
+    assert!(scratch.doq(&["did", "dishes", "--on", "2017-05-02", "-y"]).status.success());
+    let before = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(before.contains("date_due: \"2017-05-08\""));
+
+    assert!(scratch.doq(&["snooze", "dishes", "3d"]).status.success());
+    let after = fs::read_to_string(scratch.schedule()).unwrap();
+    assert_eq!(after, before.replace("date_due: \"2017-05-08\"", "date_due: \"2017-05-11\""));
+}
+
+
+#[test]
+fn skip_advances_one_period()
+{
+    let scratch = Scratch::new("skip");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2017-05-01"]).status.success());
+    let before = fs::read_to_string(scratch.schedule()).unwrap();
+
+    assert!(scratch.doq(&["skip", "dishes"]).status.success());
+    let after = fs::read_to_string(scratch.schedule()).unwrap();
+    assert_eq!(after, before.replace("date_due: \"2017-05-01\"", "date_due: \"2017-05-02\""));
+
+    assert!(scratch.doq(&["add", "party", "--repeat", "never", "--on", "2017-05-01"]).status.success());
+    let before = fs::read(scratch.schedule()).unwrap();
+    let output = scratch.doq(&["skip", "party"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("nothing to skip"));
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+}
+
+
+#[test]
+fn skip_retires_the_task_after_its_until_date()
+{
+    let scratch = Scratch::new("skip-until");
+    assert!(scratch.doq(&["add", "physio", "--repeat", "1w", "--on", "2017-05-01", "--until", "2017-05-08"]).status.success());
+
+    assert!(scratch.doq(&["skip", "physio"]).status.success());
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("date_due: \"2017-05-08\""));
+
+    let output = scratch.doq(&["skip", "physio"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("'physio' doesn't repeat after 2017-05-08, so it's finished and archived"));
+    let schedule = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(schedule.split("archived:").nth(1).unwrap().contains("name: physio"));
+    assert!(!schedule.contains("2017-05-15"));
+}
+
+
+#[test]
+fn forecast_lists_upcoming_due_dates()
+{
+    let scratch = Scratch::new("forecast");
+    assert!(scratch.doq(&["add", "bins", "--repeat", "14d", "--on", "2017-05-01"]).status.success());
+    assert!(scratch.doq(&["add", "party", "--repeat", "never", "--on", "2017-05-01"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["forecast", "bins", "--count", "3"]).stdout).into_owned();
+    assert!(output.contains("2017-05-01\n    2017-05-15\n    2017-05-29\n"));
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["forecast", "party"]).stdout).into_owned();
+    assert_eq!(output.matches("2017-").count(), 1);
+}
+
+
+#[test]
+fn agenda_groups_tasks_by_day()
+{
+    let scratch = Scratch::new("agenda");
+    assert!(scratch.doq(&["add", "water plants", "--repeat", "7d", "--on", "today"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "today"]).status.success());
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "-3d"]).status.success());
+    assert!(scratch.doq(&["add", "bins", "--repeat", "7d", "--on", "tomorrow"]).status.success());
+    assert!(scratch.doq(&["add", "dentist", "--repeat", "6m", "--on", "+30d"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["agenda"]).stdout).into_owned();
+    assert!(output.starts_with("Overdue\n    taxes (3 days overdue!)\n\nToday\n    dishes\n    water plants\n\nTomorrow\n    bins\n"));
+    assert!(!output.contains("dentist"));
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["agenda", "--days", "30"]).stdout).into_owned();
+    assert!(output.contains("dentist"));
+}
+
+
+#[test]
+fn calendar_marks_due_days()
+{
+    let scratch = Scratch::new("calendar");
+    assert!(scratch.doq(&["add", "bins", "--repeat", "14d", "--on", "2017-05-03"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["calendar", "2017-05"]).stdout).into_owned();
+    assert!(output.contains("May 2017"));
+    assert!(output.contains("\nMo  Tu  We  Th  Fr  Sa  Su\n 1   2   3!  4   5   6   7\n"));
+    assert!(output.contains(" 17* "));
+    assert!(output.contains(" 31*"));
+
+    assert!(!scratch.doq(&["calendar", "2017-13"]).status.success());
+}
+
+
+#[test]
+fn color_can_be_forced_or_disabled()
+{
+    let scratch = Scratch::new("color");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+
+    let output = scratch.command(&["--color", "always"]).output().unwrap();
+    assert!(output.stdout.contains(&0x1b));
+
+    let output = scratch.command(&["--color", "always", "--no-color"]).output().unwrap();
+    assert!(!output.stdout.contains(&0x1b));
+
+    let output = scratch.command(&[]).env("NO_COLOR", "1").output().unwrap();
+    assert!(output.status.success());
+    assert!(!output.stdout.contains(&0x1b));
+}
+
+
+#[test]
+fn tasks_due_soon_can_be_highlighted()
+{
+    let scratch = Scratch::new("warn-color");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "7d", "--on", "+2d"]).status.success());
+    let (green, yellow) = ("\x1b[38;5;10m", "\x1b[38;5;11m");
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["--color", "always"]).stdout).into_owned();
+    assert!(output.contains(green) && !output.contains(yellow));
+
+    fs::write(scratch.dir.join(".doq_config"), "warn_within_days: 3\n").unwrap();
+    let output = String::from_utf8_lossy(&scratch.doq(&["--color", "always"]).stdout).into_owned();
+    assert!(output.contains(yellow) && !output.contains(green));
+
+    fs::write(scratch.dir.join(".doq_config"), "warn_within_days: 1\n").unwrap();
+    let output = String::from_utf8_lossy(&scratch.doq(&["--color", "always"]).stdout).into_owned();
+    assert!(output.contains(green) && !output.contains(yellow));
+}
+
+
+#[test]
+fn symbols_mark_each_status()
+{
+    let scratch = Scratch::new("symbols");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "-2d"]).status.success());
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "7d", "--on", "today"]).status.success());
+    assert!(scratch.doq(&["add", "plants", "--repeat", "3d", "--on", "+2d"]).status.success());
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "+2d"]).status.success());
+    assert!(scratch.doq(&["pause", "taxes"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["--symbols", "--all", "--no-color"]).stdout).into_owned();
+    let lines: Vec<_> = output.lines().collect();
+    assert!(lines[0].starts_with("  Task"));
+    assert!(lines[1].contains("! overdue"));
+    assert!(lines[2].starts_with("  ==="));
+    assert!(lines.iter().any(|line| line.starts_with("! dishes")));
+    assert!(lines.iter().any(|line| line.starts_with("* laundry")));
+    assert!(lines.iter().any(|line| line.starts_with("  plants")));
+    assert!(lines.iter().any(|line| line.starts_with("~ taxes")));
+
+    let output = String::from_utf8_lossy(&scratch.doq(&[]).stdout).into_owned();
+    assert!(output.starts_with("Task"));
+    assert!(!output.contains("! overdue"));
+}
+
+
+#[test]
+fn porcelain_prints_tab_separated_fields()
+{
+    let scratch = Scratch::new("porcelain");
+    assert!(scratch.doq(&["add", "water plants", "--repeat", "3d", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq(&["did", "water plants", "--on", "2000-01-01", "-y"]).status.success());
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "never", "--on", "+2d"]).status.success());
+
+    let output = scratch.doq(&["--porcelain", "--color", "always"]);
+    assert!(!output.stdout.contains(&0x1b));
+    let output = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<Vec<_>> = output.lines().map(|line| line.split('\t').collect()).collect();
+
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0][..4], ["water plants", "3d", "2000-01-04", "2000-01-01"]);
+    assert!(lines[0][4].parse::<i64>().unwrap() < 0);
+    assert_eq!(lines[0][5], "overdue");
+    assert_eq!(lines[1], ["taxes", "never", lines[1][2], "-", "2", "upcoming"]);
+}
+
+
+#[test]
+fn listing_columns_stretch_to_fit()
+{
+    let scratch = Scratch::new("stretch");
+    let long_name = "clean out the gutters on the north side of the house";
+    assert!(scratch.doq(&["add", long_name, "--repeat", "1y", "--on", "2017-05-01"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2017-05-01"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).into_owned();
+    let lines: Vec<_> = output.lines().collect();
+    let long_line = lines.iter().find(|line| line.contains(long_name)).unwrap();
+    let short_line = lines.iter().find(|line| line.starts_with("dishes")).unwrap();
+
+    assert!(long_line.starts_with(&format!("{}  1y", long_name)));
+    assert_eq!(long_line.find("2017-05-01"), short_line.find("2017-05-01"));
+    assert_eq!(lines[0].find("Due on"), long_line.find("2017-05-01"));
+}
+
+
+#[test]
+fn large_day_counts_stay_aligned()
+{
+    let scratch = Scratch::new("wide-days");
+    assert!(scratch.doq(&["add", "gutters", "--repeat", "365d", "--on", "-1200d"]).status.success());
+    assert!(scratch.doq(&["did", "gutters", "--on", "-1200d", "--yes"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "-5d"]).status.success());
+    assert!(scratch.doq(&["did", "dishes", "--on", "-5d", "--yes"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).into_owned();
+    let gutters = output.lines().find(|line| line.starts_with("gutters")).unwrap();
+    let dishes = output.lines().find(|line| line.starts_with("dishes")).unwrap();
+
+    assert!(gutters.contains("1200 days ago"));
+    assert!(dishes.contains("   5 days ago"));
+    assert_eq!(gutters.find("days ago"), dishes.find("days ago"));
+    assert_eq!(gutters.find("days overdue"), dishes.find("days overdue"));
+    assert_eq!(gutters.find("365d"), dishes.find("1d").map(|i| i - 2));
+}
+
+
+#[test]
+fn paused_tasks_are_hidden_and_ignored_by_check()
+{
+    let scratch = Scratch::new("pause");
+    assert!(scratch.doq(&["add", "mow lawn", "--repeat", "7d", "--on", "-3d"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "+1d"]).status.success());
+    assert_eq!(scratch.doq(&["--check", "-q"]).status.code(), Some(2));
+
+    assert!(scratch.doq(&["pause", "mow"]).status.success());
+    let output = String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).into_owned();
+    assert!(!output.contains("mow lawn"));
+    assert_eq!(scratch.doq(&["--check", "-q"]).status.code(), Some(0));
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["list", "--all"]).stdout).into_owned();
+    assert!(output.lines().any(|line| line.starts_with("mow lawn") && line.contains("(Paused)")));
+    assert_eq!(scratch.doq(&["--check", "-q", "--all"]).status.code(), Some(0));
+
+    assert!(scratch.doq(&["resume", "mow"]).status.success());
+    assert_eq!(scratch.doq(&["--check", "-q"]).status.code(), Some(2));
+}
+
+
+#[test]
+fn tasks_can_be_targeted_by_id()
+{
+    let scratch = Scratch::new("ids");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2017-05-01"]).status.success());
+    assert!(scratch.doq(&["add", "dusting", "--repeat", "7d", "--on", "2017-05-01"]).status.success());
+
+    let output = scratch.doq(&["--format", "json"]);
+    let tasks: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let dusting = tasks.as_array().unwrap().iter().find(|task| task["name"] == "dusting").unwrap();
+    let id = dusting["id"].as_str().unwrap();
+
+    assert!(scratch.doq(&["skip", "--id", id]).status.success());
+    let schedule = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(schedule.contains("date_due: \"2017-05-08\""));
+    assert!(schedule.contains(id));
+
+    assert!(scratch.doq(&["snooze", "--id", id, "3d"]).status.success());
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("date_due: \"2017-05-11\""));
+    assert!(!scratch.doq(&["snooze", "--id", id]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["export", "--ics"]).stdout).into_owned();
+    assert!(output.contains(&format!("UID:{}@doq\r\n", id)));
+
+    assert!(!scratch.doq(&["skip", "--id", "not-an-id"]).status.success());
+    assert!(!scratch.doq(&["skip"]).status.success());
+}
+
+
+#[test]
+fn duplicate_names_are_rejected_unless_allowed()
+{
+    let scratch = Scratch::new("duplicates");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+    assert!(!scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "7d", "--allow-duplicates"]).status.success());
+
+    let output = scratch.doq(&["list"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("More than one task is named 'dishes'"));
+    assert!(stderr(&output).contains("--allow-duplicates"));
+
+    let output = scratch.doq(&["--format", "json", "--allow-duplicates"]);
+    let tasks: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let weekly = tasks.as_array().unwrap().iter().find(|task| task["repeat"] == "7d").unwrap();
+
+    assert!(scratch.doq(&["pause", "--id", weekly["id"].as_str().unwrap(), "--allow-duplicates"]).status.success());
+    let output = scratch.doq(&["--format", "json", "--allow-duplicates"]);
+    let tasks: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(tasks.as_array().unwrap().len(), 1);
+    assert_eq!(tasks[0]["repeat"], "1d");
+}
+
+
+#[test]
+fn complete_prints_matching_task_names()
+{
+    let scratch = Scratch::new("complete");
+    let output = scratch.doq(&["__complete", "wa"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(!scratch.schedule().exists());
+
+    assert!(scratch.doq(&["add", "water plants", "--repeat", "7d"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["__complete", "wa"]).stdout).into_owned();
+    assert_eq!(output, "water plants\n");
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["__complete"]).stdout).into_owned();
+    assert_eq!(output, "water plants\ndishes\n");
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["completions", "bash"]).stdout).into_owned();
+    assert!(output.contains("doq __complete"));
+}
+
+
+#[test]
+fn json_format_lists_tasks()
+{
+    let scratch = Scratch::new("json");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "today"]).status.success());
+
+    let output = scratch.doq(&["--format", "json"]);
+    let tasks: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(tasks[0]["name"], "dishes");
+    assert_eq!(tasks[0]["days_until_due"], 0);
+    assert_eq!(tasks[0]["status"], "due_today");
+    assert_eq!(tasks[0]["at_least"], false);
+}
+
+
+#[test]
+fn export_csv_quotes_names()
+{
+    let scratch = Scratch::new("csv");
+    assert!(scratch.doq(&["add", "wash, dry, fold", "--repeat", "7d", "--on", "today"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["export", "--csv"]).stdout).into_owned();
+    let mut lines = output.lines();
+    assert_eq!(lines.next(), Some("name,repeat,at_least,date_completed,date_due,days_until_due"));
+    assert!(lines.next().unwrap().starts_with("\"wash, dry, fold\",7d,false,,"));
+    assert_eq!(lines.next(), None);
+
+    let path = scratch.dir.join("export.csv");
+    assert!(scratch.doq(&["export", "--csv", "--output", path.to_str().unwrap()]).status.success());
+    assert_eq!(fs::read_to_string(path).unwrap(), output);
+}
+
+
+#[test]
+fn export_ics_emits_events()
+{
+    let scratch = Scratch::new("ics");
+    assert!(scratch.doq(&["add", "wash, dry; fold", "--repeat", "14d", "--on", "2017-05-27"]).status.success());
+    assert!(scratch.doq(&["add", "party", "--repeat", "never", "--on", "2017-06-01"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["export", "--ics"]).stdout).into_owned();
+
+    assert!(output.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(output.ends_with("END:VCALENDAR\r\n"));
+    assert!(!output.replace("\r\n", "").contains('\n'));
+    assert!(output.contains("SUMMARY:wash\\, dry\\; fold\r\nRRULE:FREQ=WEEKLY;INTERVAL=2\r\n"));
+    assert!(output.contains("DTSTART;VALUE=DATE:20170527\r\n"));
+    assert!(output.contains("SUMMARY:party\r\nEND:VEVENT\r\n"));
+
+    assert!(scratch.doq(&["add", "rent", "--repeat", "m1", "--on", "2017-06-01"]).status.success());
+    assert!(scratch.doq(&["add", "invoice", "--repeat", "m30", "--on", "2017-06-30"]).status.success());
+    let output = String::from_utf8_lossy(&scratch.doq(&["export", "--ics"]).stdout).into_owned();
+    assert!(output.contains("SUMMARY:rent\r\nRRULE:FREQ=MONTHLY;BYMONTHDAY=1\r\n"));
+    assert!(output.contains("SUMMARY:invoice\r\nRRULE:FREQ=MONTHLY;BYMONTHDAY=28,29,30;BYSETPOS=-1\r\n"));
+
+    assert!(scratch.doq(&["add", "book club", "--repeat", "2tue", "--on", "2017-06-13"]).status.success());
+    assert!(scratch.doq(&["add", "quiz", "--repeat", "5fri", "--on", "2017-06-30"]).status.success());
+    let output = String::from_utf8_lossy(&scratch.doq(&["export", "--ics"]).stdout).into_owned();
+    assert!(output.contains("SUMMARY:book club\r\nRRULE:FREQ=MONTHLY;BYDAY=2TU\r\n"));
+    assert!(output.contains("SUMMARY:quiz\r\nRRULE:FREQ=MONTHLY;BYDAY=-1FR\r\n"));
+}
+
+
+#[test]
+fn listing_filters_by_tag()
+{
+    let scratch = Scratch::new("tags");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--tag", "home"]).status.success());
+    assert!(scratch.doq(&["add", "timesheet", "--repeat", "7d", "--tag", "work", "--tag", "admin"]).status.success());
+    assert!(scratch.doq(&["add", "stretch", "--repeat", "1d"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["--tag", "work"]).stdout).into_owned();
+    assert!(output.contains("timesheet [work, admin]"));
+    assert!(!output.contains("dishes"));
+    assert!(!output.contains("stretch"));
+
+    assert!(scratch.doq(&["edit", "dishes", "--tag", "chores"]).status.success());
+    let output = String::from_utf8_lossy(&scratch.doq(&["--tag", "chores"]).stdout).into_owned();
+    assert!(output.contains("dishes [chores]"));
+}
+
+
+#[test]
+fn listing_sorts_by_priority_then_due_date()
+{
+    let scratch = Scratch::new("priority");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "today"]).status.success());
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "+30d", "--priority", "5"]).status.success());
+    assert!(scratch.doq(&["add", "bins", "--repeat", "7d", "--on", "+2d", "--priority", "5"]).status.success());
+    assert!(scratch.doq(&["add", "plants", "--repeat", "3d", "--on", "+1d", "--priority", "1"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["--sort", "priority"]).stdout).into_owned();
+    let positions: Vec<_> = ["bins", "taxes", "plants", "dishes"].iter().map(|name| output.find(name).unwrap()).collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+
+    let output = String::from_utf8_lossy(&scratch.doq(&[]).stdout).into_owned();
+    assert!(output.find("dishes").unwrap() < output.find("plants").unwrap());
+}
+
+
+#[test]
+fn tasks_due_the_same_day_are_listed_alphabetically()
+{
+    let scratch = Scratch::new("ties");
+    for name in &["zebra", "mango", "apple"]
+    {
+        assert!(scratch.doq(&["add", name, "--repeat", "1d", "--on", "+1d"]).status.success());
+    }
+
+    let output = String::from_utf8_lossy(&scratch.doq(&[]).stdout).into_owned();
+    let positions: Vec<_> = ["apple", "mango", "zebra"].iter().map(|name| output.find(name).unwrap()).collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+
+#[test]
+fn notes_are_shown_and_cleared()
+{
+    let scratch = Scratch::new("notes");
+    assert!(scratch.doq(&["add", "hoover", "--repeat", "7d", "--note", "use the blue filter"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&[]).stdout).into_owned();
+    assert!(!output.contains("blue filter"));
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["--notes"]).stdout).into_owned();
+    assert!(output.contains("    use the blue filter"));
+
+    assert!(scratch.doq(&["edit", "hoover", "--note", ""]).status.success());
+    assert!(!fs::read_to_string(scratch.schedule()).unwrap().contains("blue filter"));
+}
+
+
+#[test]
+fn stats_reports_adherence()
+{
+    let scratch = Scratch::new("stats");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "7d", "--on", "2017-05-01"]).status.success());
+    assert!(scratch.doq(&["did", "dishes", "--on", "2017-05-01", "-y"]).status.success());
+    assert!(scratch.doq(&["did", "dishes", "--on", "2017-05-10", "-y"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["stats", "dish"]).stdout).into_owned();
+    assert!(output.contains("Completions:      2"));
+    assert!(output.contains("Late:             1"));
+    assert!(output.contains("Average interval: 9.0 days"));
+
+    let output = scratch.doq(&["--format", "json", "stats"]);
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stats[0]["name"], "dishes");
+    assert_eq!(stats[0]["stats"]["on_time"], 1);
+    assert_eq!(stats[0]["stats"]["streak"], 0);
+}
+
+
+#[test]
+fn check_exits_with_overdue_status()
+{
+    let scratch = Scratch::new("check");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "today"]).status.success());
+    assert_eq!(scratch.doq(&["--check"]).status.code(), Some(0));
+
+    assert!(scratch.doq(&["add", "bins", "--repeat", "7d", "--on", "yesterday"]).status.success());
+    assert_eq!(scratch.doq(&["--check"]).status.code(), Some(2));
+    assert_eq!(scratch.doq(&[]).status.code(), Some(0));
+}
+
+
+#[test]
+fn quiet_suppresses_the_listing()
+{
+    let scratch = Scratch::new("quiet");
+
+    let output = scratch.doq(&["add", "dishes", "--repeat", "1d", "--quiet"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = scratch.doq(&["--quiet", "did", "dishes", "-y"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = scratch.doq(&["--quiet"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("dishes"));
+
+    let output = scratch.doq(&["--quiet", "--check"]);
+    assert!(output.stdout.is_empty());
+}
+
+
+#[test]
+fn list_filters_compose()
+{
+    let scratch = Scratch::new("list");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "today", "--tag", "home"]).status.success());
+    assert!(scratch.doq(&["add", "bins", "--repeat", "7d", "--on", "-2d", "--tag", "home"]).status.success());
+    assert!(scratch.doq(&["add", "timesheet", "--repeat", "7d", "--on", "-1d", "--tag", "work"]).status.success());
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "+60d", "--tag", "home"]).status.success());
+    assert!(scratch.doq(&["add", "plants", "--repeat", "3d", "--on", "+2d", "--tag", "home"]).status.success());
+
+    let list = |args: &[&str]| String::from_utf8_lossy(&scratch.doq(args).stdout).into_owned();
+
+    let output = list(&["list", "--overdue"]);
+    assert!(output.contains("bins") && output.contains("timesheet"));
+    assert!(!output.contains("dishes") && !output.contains("taxes") && !output.contains("plants"));
+
+    let output = list(&["list", "--overdue", "--due-today", "--tag", "home"]);
+    assert!(output.contains("bins") && output.contains("dishes"));
+    assert!(!output.contains("timesheet") && !output.contains("taxes") && !output.contains("plants"));
+
+    let output = list(&["list", "--due-within", "7", "--tag", "home"]);
+    assert!(output.contains("bins") && output.contains("dishes") && output.contains("plants"));
+    assert!(!output.contains("timesheet") && !output.contains("taxes"));
+
+    assert_eq!(list(&["list"]), list(&[]));
+}
+
+
+#[test]
+fn writes_keep_one_backup()
+{
+    let scratch = Scratch::new("backup");
+    let backup = scratch.dir.join("schedule.yaml.bak");
+
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+    let first = fs::read(scratch.schedule()).unwrap();
+
+    assert!(scratch.doq(&["add", "bins", "--repeat", "7d"]).status.success());
+    assert_eq!(fs::read(&backup).unwrap(), first);
+    let second = fs::read(scratch.schedule()).unwrap();
+
+    assert!(scratch.doq(&["add", "plants", "--repeat", "3d"]).status.success());
+    assert_eq!(fs::read(&backup).unwrap(), second);
+}
+
+
+#[test]
+fn config_schedule_file_expands_home()
+{
+    let scratch = Scratch::new("config");
+    fs::create_dir_all(scratch.dir.join(".config/doq")).unwrap();
+    fs::write(scratch.dir.join(".config/doq/config.yaml"), "schedule_file: ~/chores.yaml\n").unwrap();
+
+    assert!(scratch.doq_without_file(&["add", "dishes", "--repeat", "1d"]).status.success());
+    assert!(fs::read_to_string(scratch.dir.join("chores.yaml")).unwrap().contains("dishes"));
+    assert!(!scratch.dir.join(".doq").exists());
+}
+
+
+#[test]
+fn toml_config_and_schedule_are_supported()
+{
+    let scratch = Scratch::new("toml");
+    fs::write(scratch.dir.join(".doq_config"), "schedule_file = \"~/chores.toml\"\n").unwrap();
+
+    assert!(scratch.doq_without_file(&["add", "dishes", "--repeat", "1d", "--on", "2017-05-01"]).status.success());
+    assert!(scratch.doq_without_file(&["did", "dishes", "--on", "2017-05-01", "--yes"]).status.success());
+
+    let schedule = fs::read_to_string(scratch.dir.join("chores.toml")).unwrap();
+    assert!(schedule.contains("name = \"dishes\""));
+    assert!(schedule.contains("date_due = \"2017-05-02\""));
+
+    let output = String::from_utf8_lossy(&scratch.doq_without_file(&["list"]).stdout).into_owned();
+    assert!(output.lines().any(|line| line.starts_with("dishes") && line.contains("2017-05-02")));
+
+    let output = String::from_utf8_lossy(&scratch.doq_without_file(&["__complete", "dis"]).stdout).into_owned();
+    assert_eq!(output, "dishes\n");
+}
+
+
+#[test]
+fn default_schedule_is_in_home()
+{
+    let scratch = Scratch::new("default-schedule");
+    fs::create_dir_all(scratch.dir.join("elsewhere")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_doq"))
+        .args(["add", "dishes", "--repeat", "1d"])
+        .env("HOME", &scratch.dir)
+        .env("XDG_CONFIG_HOME", scratch.dir.join(".config"))
+        .current_dir(scratch.dir.join("elsewhere"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(fs::read_to_string(scratch.dir.join(".doq")).unwrap().contains("dishes"));
+}
+
+
+#[test]
+fn missing_directories_are_created()
+{
+    let scratch = Scratch::new("create-dirs");
+    let output = scratch.doq_without_file(&["--file", "some/new/dir/schedule.yaml", "add", "dishes", "--repeat", "1d"]);
+
+    assert!(output.status.success());
+    assert!(fs::read_to_string(scratch.dir.join("some/new/dir/schedule.yaml")).unwrap().contains("dishes"));
+}
+
+
+#[test]
+fn dash_file_reads_stdin_and_writes_stdout()
+{
+    use std::io::Write;
+
+    let scratch = Scratch::new("stdio");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2000-01-01"]).status.success());
+    let input = fs::read_to_string(scratch.schedule()).unwrap();
+
+    let mut child = scratch.command_without_file(&["--file", "-", "did", "dishes", "--on", "2000-01-02", "--yes"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("name: dishes"));
+    assert!(stdout.contains("date_completed: \"2000-01-02\""));
+    assert!(stdout.contains("date_due: \"2000-01-03\""));
+    assert!(!stdout.contains("Last completed"));
+    assert!(!scratch.dir.join("-").exists());
+    assert_eq!(fs::read_to_string(scratch.schedule()).unwrap(), input);
+
+    let output = scratch.doq_without_file(&["--file", "-", "__complete", "dis"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+
+#[test]
+fn dash_file_refuses_to_prompt()
+{
+    use std::io::Write;
+
+    let scratch = Scratch::new("stdio-prompt");
+    let mut child = scratch.command_without_file(&["--file", "-", "remove", "dishes"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"tasks:\n  - name: dishes\n    date_completed: ~\n    date_due: \"2000-01-01\"\n    repeat: 1d\n    at_least: false\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Pass --yes"));
+    assert!(output.stdout.is_empty());
+}
+
+
+#[test]
+fn edit_can_make_a_task_one_off_and_clear_completion()
+{
+    let scratch = Scratch::new("edit-never");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq(&["did", "dishes", "--on", "2000-01-01", "-y"]).status.success());
+
+    assert!(scratch.doq(&["edit", "dishes", "--repeat", "never", "--clear-completed"]).status.success());
+    let contents = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(contents.contains("repeat: never"));
+    assert!(contents.contains("date_completed: ~"));
+    assert!(contents.contains("- date: \"2000-01-01\""));
+
+    let before = fs::read(scratch.schedule()).unwrap();
+    let output = scratch.doq(&["edit", "dishes", "--at-least", "true"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Cannot specify --at-least and --repeat never"));
+
+    assert!(scratch.doq(&["edit", "dishes", "--repeat", "2d", "--at-least", "true"]).status.success());
+    let output = scratch.doq(&["edit", "dishes", "--repeat", "never"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Pass --at-least false"));
+
+    assert!(scratch.doq(&["edit", "dishes", "--repeat", "never", "--at-least", "false"]).status.success());
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+}
+
+
+#[test]
+fn edit_rejects_renaming_onto_another_task()
+{
+    let scratch = Scratch::new("edit-rename");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "1w"]).status.success());
+
+    let before = fs::read(scratch.schedule()).unwrap();
+    let output = scratch.doq(&["edit", "laundry", "--rename", "dishes"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Task already exists"));
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+
+    assert!(scratch.doq(&["edit", "dishes", "--rename", "dishes"]).status.success());
+    assert!(scratch.doq(&["edit", "laundry", "--rename", "Dishes"]).status.success());
+    assert!(scratch.doq(&["--allow-duplicates", "edit", "Dishes", "--rename", "dishes"]).status.success());
+}
+
+
+#[test]
+fn add_can_record_a_previous_completion()
+{
+    let scratch = Scratch::new("done-on");
+    assert!(scratch.doq(&["add", "x", "--repeat", "7d", "--done-on", "2024-01-01"]).status.success());
+    let contents = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(contents.contains("date_completed: \"2024-01-01\""));
+    assert!(contents.contains("date_due: \"2024-01-08\""));
+
+    assert!(scratch.doq(&["add", "y", "--repeat", "7d", "--on", "2024-01-03", "--done-on", "2024-01-05"]).status.success());
+    assert!(scratch.doq(&["add", "z", "--repeat", "7d", "--on", "2024-01-03", "--done-on", "2024-01-05", "--at-least"]).status.success());
+    let output = String::from_utf8(scratch.doq(&["--porcelain"]).stdout).unwrap();
+    assert!(output.contains("y\t7d\t2024-01-10\t2024-01-05"));
+    assert!(output.contains("z\t7d\t2024-01-12\t2024-01-05"));
+
+    let output = scratch.doq(&["add", "once", "--repeat", "never", "--done-on", "2024-01-01"]);
+    assert!(!output.status.success());
+}
+
+
+#[test]
+fn migrate_reports_upgraded_tasks()
+{
+    let scratch = Scratch::new("migrate");
+    let old = "tasks:\n  - name: dishes\n    frequency_days: 1\n    last_completed: \"2017-05-01\"\n  - name: hoover\n    date_completed: ~\n    date_due: \"2017-05-01\"\n    repeat: 7d\n    at_least: false\n";
+    fs::write(scratch.schedule(), old).unwrap();
+
+    let output = scratch.doq(&["migrate", "--dry-run"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.contains("Upgraded 'dishes'"));
+    assert!(!stdout.contains("hoover"));
+    assert_eq!(fs::read_to_string(scratch.schedule()).unwrap(), old);
+
+    assert!(scratch.doq(&["migrate"]).status.success());
+    let contents = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(!contents.contains("frequency_days"));
+    assert!(contents.contains("date_due: \"2017-05-02\""));
+
+    let output = scratch.doq(&["migrate"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No tasks needed upgrading"));
+}
+
+
+#[test]
+fn migrate_refuses_to_drop_tasks()
+{
+    let scratch = Scratch::new("migrate-fail");
+    let old = "tasks:\n  - name: dishes\n    frequency_days: 0\n    last_completed: \"2017-05-01\"\n";
+    fs::write(scratch.schedule(), old).unwrap();
+
+    let output = scratch.doq(&["migrate"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Couldn't upgrade 'dishes'"));
+    assert_eq!(fs::read_to_string(scratch.schedule()).unwrap(), old);
+
+    let output = scratch.doq(&[]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Failed to upversion old tasks in schedule: 'dishes'"));
+    assert_eq!(fs::read_to_string(scratch.schedule()).unwrap(), old);
+}
+
+
+#[test]
+fn dry_run_leaves_the_schedule_untouched()
+{
+    let scratch = Scratch::new("dry-run");
+    let output = scratch.doq(&["--dry-run", "add", "dishes", "--repeat", "1d"]);
+    assert!(output.status.success());
+    assert!(!scratch.schedule().exists());
+
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2000-01-01"]).status.success());
+    let before = fs::read(scratch.schedule()).unwrap();
+
+    let output = scratch.doq(&["add", "laundry", "--repeat", "7d", "--dry-run"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.contains("Dry run"));
+    assert!(stdout.contains("laundry"));
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+
+    let output = scratch.doq(&["--dry-run", "did", "dishes", "--on", "2000-01-01", "-y"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("2000-01-02"));
+
+    let output = scratch.doq(&["--quiet", "--dry-run", "snooze", "dishes", "3d"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.contains("'dishes' would be:"));
+    assert!(stdout.contains("date_due: \"2000-01-04\""));
+    assert!(String::from_utf8_lossy(&scratch.doq(&["--quiet", "--dry-run", "remove", "dishes", "-y"]).stdout).contains("'dishes' would be removed"));
+    for args in &[&["edit", "dishes", "--repeat", "2d"][..], &["remove", "dishes", "-y"], &["snooze", "dishes", "3d"], &["skip", "dishes"]]
+    {
+        let mut args = args.to_vec();
+        args.push("--dry-run");
+        assert!(scratch.doq(&args).status.success());
+    }
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+}
+
+
+#[test]
+fn add_can_ask_for_missing_details()
+{
+    use std::io::Write;
+
+    let scratch = Scratch::new("interactive");
+    let output = scratch.doq(&["add"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("No task name given"));
+
+    let output = scratch.doq(&["add", "dishes"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("No --repeat given"));
+
+    let mut child = scratch.command(&["add", "--interactive"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"  \n dishes \n5x\n3d\nsoon\n2000-01-01\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Task names can't be empty"));
+    assert!(stderr(&output).contains("Expected a suffix"));
+    assert!(stderr(&output).contains("Invalid date 'soon'"));
+    let contents = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(contents.contains("name: dishes\n"));
+    assert!(contents.contains("repeat: 3d"));
+    assert!(contents.contains("date_due: \"2000-01-01\""));
+
+    let output = scratch.doq(&["add", "laundry", "--interactive"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("No answer given"));
+}
+
+
+#[test]
+fn did_completes_several_tasks_at_once()
+{
+    let scratch = Scratch::new("did-many");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "7d", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq(&["add", "party", "--repeat", "never", "--on", "2000-01-01"]).status.success());
+    let before = fs::read(scratch.schedule()).unwrap();
+
+    let output = scratch.doq(&["did", "dishes", "--id", "00000000-0000-0000-0000-000000000000", "-y"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("cannot be used with"));
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+
+    let output = scratch.doq(&["did", "dishes", "laundry", "--on", "2000-01-01"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Mark tasks 'dishes', 'laundry' as done on 2000-01-01?"));
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+
+    let output = scratch.doq(&["did", "dishes", "party", "laundry", "dishes", "--on", "2000-01-01", "-y"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.contains("'dishes' done. Next due 2000-01-02"));
+    assert!(stdout.contains("'laundry' done. Next due 2000-01-08"));
+    assert!(stdout.contains("'party' done and archived"));
+
+    let contents = fs::read_to_string(scratch.schedule()).unwrap();
+    assert_eq!(contents.matches("date_completed: \"2000-01-01\"").count(), 3);
+    assert!(contents.contains("date_due: \"2000-01-02\""));
+    assert!(contents.contains("date_due: \"2000-01-08\""));
+    assert!(contents.find("archived").unwrap() < contents.find("party").unwrap());
+}
+
+
+#[test]
+fn import_adds_tasks_from_a_file()
+{
+    let scratch = Scratch::new("import");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+
+    let tasks = scratch.dir.join("tasks.yaml");
+    fs::write(&tasks, "- name: dishes\n  repeat: 2d\n- name: laundry\n  repeat: weekly\n  on: 2000-01-01\n  tags: [chores]\n- name: haircut\n  repeat: 6w\n  at_least: true\n").unwrap();
+    let output = scratch.doq(&["import", tasks.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.contains("Skipping 'dishes'"));
+    assert!(stdout.contains("Imported 2 tasks"));
+
+    let contents = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(contents.contains("name: laundry"));
+    assert!(contents.contains("date_due: \"2000-01-01\""));
+    assert!(contents.contains("- chores"));
+    assert!(contents.contains("at_least: true"));
+
+    let tasks = scratch.dir.join("tasks.json");
+    fs::write(&tasks, r#"[{"name": "plants", "repeat": "3d"}, {"name": "bins", "repeat": "3x"}]"#).unwrap();
+    let before = fs::read(scratch.schedule()).unwrap();
+    let output = scratch.doq(&["import", tasks.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Entry 2 can't be imported: 'bins'"));
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+
+    fs::write(&tasks, r#"[{"name": "plants", "repeat": "3d"}, {"name": "bins", "repeat": "1w", "on": "someday"}]"#).unwrap();
+    assert!(!scratch.doq(&["import", tasks.to_str().unwrap()]).status.success());
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+}
+
+
+#[test]
+fn merge_keeps_the_most_recently_completed_copy()
+{
+    let scratch = Scratch::new("merge");
+    let other = scratch.dir.join("other.yaml");
+    let other_str = other.to_str().unwrap();
+
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq(&["did", "dishes", "--on", "2000-01-03", "-y"]).status.success());
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "7d", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq(&["did", "laundry", "--on", "2000-01-01", "-y"]).status.success());
+
+    assert!(scratch.doq_without_file(&["--file", other_str, "add", "dishes", "--repeat", "1d", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq_without_file(&["--file", other_str, "did", "dishes", "--on", "2000-01-02", "-y"]).status.success());
+    assert!(scratch.doq_without_file(&["--file", other_str, "add", "laundry", "--repeat", "7d", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq_without_file(&["--file", other_str, "did", "laundry", "--on", "2000-01-05", "-y"]).status.success());
+    assert!(scratch.doq_without_file(&["--file", other_str, "add", "plants", "--repeat", "3d"]).status.success());
+
+    let output = scratch.doq(&["merge", other_str]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.contains("'dishes': kept this copy (completed 2000-01-03)"));
+    assert!(stdout.contains("'laundry': kept the copy from"));
+    assert!(stdout.contains("Added 1 task from"));
+
+    let output = String::from_utf8(scratch.doq(&["--porcelain"]).stdout).unwrap();
+    assert!(output.contains("dishes\t1d\t2000-01-04\t2000-01-03"));
+    assert!(output.contains("laundry\t7d\t2000-01-08\t2000-01-05"));
+    assert!(output.contains("plants\t3d"));
+    assert_eq!(output.lines().count(), 3);
+}
+
+
+#[test]
+fn diff_compares_two_schedules()
+{
+    let scratch = Scratch::new("diff");
+    let backup = scratch.dir.join("backup.yaml");
+    let backup_str = backup.to_str().unwrap();
+
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "7d", "--on", "2000-01-01"]).status.success());
+    fs::copy(scratch.schedule(), &backup).unwrap();
+    assert_eq!(String::from_utf8_lossy(&scratch.doq(&["diff", backup_str]).stdout), "No differences\n");
+
+    assert!(scratch.doq(&["did", "dishes", "--on", "2000-01-01", "-y"]).status.success());
+    assert!(scratch.doq(&["remove", "laundry", "-y"]).status.success());
+    assert!(scratch.doq(&["add", "plants", "--repeat", "3d"]).status.success());
+
+    let output = scratch.doq(&["diff", backup_str]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.contains("- plants\n"));
+    assert!(stdout.contains("+ laundry\n"));
+    assert!(stdout.contains("~ dishes\n    date_due: 2000-01-02 -> 2000-01-01\n"));
+    assert!(stdout.contains("    date_completed: 2000-01-01 -> never\n"));
+
+    let output = scratch.doq(&["--format", "json", "diff", backup_str]);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["only_in_this"], serde_json::json!(["plants"]));
+    assert_eq!(json["only_in_other"], serde_json::json!(["laundry"]));
+    assert_eq!(json["changed"][0]["name"], "dishes");
+    assert_eq!(json["changed"][0]["changes"][0]["field"], "date_due");
+}
+
+
+#[test]
+fn move_relocates_a_task_to_another_schedule()
+{
+    let scratch = Scratch::new("move");
+    let work = scratch.dir.join("work.yaml");
+    let work_str = work.to_str().unwrap();
+
+    assert!(scratch.doq(&["add", "timesheet", "--repeat", "7d", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq(&["did", "timesheet", "--on", "2000-01-03", "-y"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+
+    let output = scratch.doq(&["move", "times", "--to", work_str]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Moved task 'timesheet' to"));
+
+    assert!(!fs::read_to_string(scratch.schedule()).unwrap().contains("timesheet"));
+    let output = String::from_utf8(scratch.doq_without_file(&["--file", work_str, "--porcelain"]).stdout).unwrap();
+    assert!(output.starts_with("timesheet\t7d\t2000-01-08\t2000-01-03"));
+    assert_eq!(output.lines().count(), 1);
+
+    assert!(scratch.doq(&["add", "timesheet", "--repeat", "7d"]).status.success());
+    let before = (fs::read(scratch.schedule()).unwrap(), fs::read(&work).unwrap());
+    let output = scratch.doq(&["move", "timesheet", "--to", work_str]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("already has a task named 'timesheet'"));
+    assert_eq!((fs::read(scratch.schedule()).unwrap(), fs::read(&work).unwrap()), before);
+}
+
+
+#[test]
+fn next_shows_the_most_urgent_task()
+{
+    let scratch = Scratch::new("next");
+    assert_eq!(String::from_utf8_lossy(&scratch.doq(&["next"]).stdout), "nothing due\n");
+
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "+30d"]).status.success());
+    assert_eq!(String::from_utf8_lossy(&scratch.doq(&["next"]).stdout), "nothing due\n");
+    assert_eq!(String::from_utf8_lossy(&scratch.doq(&["next", "--days", "30"]).stdout), "taxes: due in 30 days\n");
+
+    assert!(scratch.doq(&["add", "plants", "--repeat", "3d", "--on", "+1d"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "-2d"]).status.success());
+    assert_eq!(String::from_utf8_lossy(&scratch.doq(&["next"]).stdout), "dishes: 2 days overdue\n");
+
+    let output = scratch.doq(&["--format", "json", "next"]);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json.as_array().unwrap().len(), 1);
+    assert_eq!(json[0]["name"], "dishes");
+    assert_eq!(json[0]["days_until_due"], -2);
+}
+
+
+#[test]
+fn listing_ends_with_a_summary()
+{
+    let scratch = Scratch::new("summary");
+    for &(name, on) in &[("dishes", "-2d"), ("bins", "-1d"), ("laundry", "today"), ("plants", "+1d"), ("taxes", "+30d"), ("car", "+10d")]
+    {
+        assert!(scratch.doq(&["add", name, "--repeat", "7d", "--on", on]).status.success());
+    }
+    assert!(scratch.doq(&["pause", "car"]).status.success());
+
+    let output = String::from_utf8(scratch.doq(&[]).stdout).unwrap();
+    assert_eq!(output.lines().last(), Some("2 overdue, 1 due today, 2 upcoming"));
+
+    let output = String::from_utf8(scratch.doq(&["--all"]).stdout).unwrap();
+    assert_eq!(output.lines().last(), Some("2 overdue, 1 due today, 2 upcoming, 1 paused"));
+
+    let output = String::from_utf8(scratch.doq(&["--porcelain"]).stdout).unwrap();
+    assert!(!output.contains("overdue,"));
+    let output = String::from_utf8(scratch.doq(&["--quiet"]).stdout).unwrap();
+    assert!(output.contains("dishes"));
+    assert!(!output.contains("overdue,"));
+}
+
+
+#[test]
+fn export_markdown_uses_list_filters()
+{
+    let scratch = Scratch::new("markdown");
+    assert!(scratch.doq(&["add", "dishes|cups", "--repeat", "1d", "--on", "-2d", "--tag", "kitchen"]).status.success());
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "7d", "--on", "+3d", "--at-least"]).status.success());
+    assert!(scratch.doq(&["add", "party", "--repeat", "never", "--on", "today", "--tag", "kitchen"]).status.success());
+
+    let output = scratch.command(&["--color", "always", "export", "--markdown"]).output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!output.stdout.contains(&0x1b));
+    let output = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines[0], "| Task | Repeat | Last completed | Due on | Status |");
+    assert_eq!(lines[1], "| --- | --- | --- | --- | --- |");
+    assert!(lines[2].starts_with("| dishes\\|cups | 1d | Never | "));
+    assert!(lines[2].ends_with(" | 2 days overdue |"));
+    assert!(lines[3].starts_with("| party | -- | Never |"));
+    assert!(lines[4].starts_with("| laundry | at least 7d | Never |"));
+    assert_eq!(lines.len(), 5);
+
+    let output = String::from_utf8(scratch.doq(&["export", "--markdown", "--overdue"]).stdout).unwrap();
+    assert_eq!(output.lines().count(), 3);
+
+    let output = String::from_utf8(scratch.doq(&["export", "--markdown", "--tag", "kitchen"]).stdout).unwrap();
+    assert_eq!(output.lines().count(), 4);
+
+    let output = String::from_utf8(scratch.doq(&["export", "--csv", "--tag", "kitchen"]).stdout).unwrap();
+    assert_eq!(output.lines().count(), 3);
+    let output = String::from_utf8(scratch.doq(&["export", "--ics", "--overdue"]).stdout).unwrap();
+    assert_eq!(output.matches("BEGIN:VEVENT").count(), 1);
+}
+
+
+#[test]
+fn export_html_writes_a_styled_report()
+{
+    let scratch = Scratch::new("html");
+    assert!(scratch.doq(&["add", "dishes <daily>", "--repeat", "1d", "--on", "-2d"]).status.success());
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "7d", "--on", "today"]).status.success());
+    assert!(scratch.doq(&["add", "vacuum", "--repeat", "7d", "--on", "+3d"]).status.success());
+
+    let output = scratch.doq(&["export", "--html"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = String::from_utf8(output.stdout).unwrap();
+    assert!(output.starts_with("<!DOCTYPE html>"));
+    assert!(output.contains("<style>"));
+    assert!(output.contains("<p>1 overdue, 1 due today, 1 upcoming</p>"));
+    assert!(output.contains("<tr class=\"overdue\"><td>dishes &lt;daily&gt;</td><td>1d</td><td>Never</td>"));
+    assert!(output.contains("<tr class=\"due-today\"><td>laundry</td>"));
+    assert!(output.contains("<tr class=\"upcoming\"><td>vacuum</td>"));
+    assert!(output.trim_end().ends_with("</html>"));
+
+    let output = String::from_utf8(scratch.doq(&["export", "--html", "--overdue"]).stdout).unwrap();
+    assert!(output.contains("<p>1 overdue, 0 due today, 0 upcoming</p>"));
+    assert!(!output.contains("laundry"));
+}
+
+
+#[test]
+fn parse_errors_report_their_location()
+{
+    let scratch = Scratch::new("parse_errors");
+    fs::write(scratch.schedule(), "tasks:\n  - name: dishes\n    repeat: 1d\n   bad: [\n").unwrap();
+
+    let output = scratch.doq(&["list"]);
+    assert!(!output.status.success());
+    let expected = format!("doq: error: Failed to parse schedule {} at line 4, column ", scratch.schedule().display());
+    assert!(stderr(&output).starts_with(&expected), "{}", stderr(&output));
+    assert!(stderr(&output).contains("did not find expected '-' indicator"));
+
+    fs::remove_file(scratch.schedule()).unwrap();
+    fs::write(scratch.dir.join(".doq_config"), "timezone: utc\nwarn_within_days: [3\n").unwrap();
+
+    let output = scratch.doq(&["list"]);
+    assert!(!output.status.success());
+    let expected = format!("doq: error: Failed to parse config {} at line 3, column ", scratch.dir.join(".doq_config").display());
+    assert!(stderr(&output).starts_with(&expected), "{}", stderr(&output));
+}
+
+
+#[test]
+fn broken_config_suggests_deleting_it()
+{
+    let scratch = Scratch::new("broken_config");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+    fs::write(scratch.dir.join(".doq_config"), "warn_within_days: soon\n").unwrap();
+
+    let output = scratch.doq(&["list"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains(&format!("Failed to parse config {}", scratch.dir.join(".doq_config").display())));
+    assert!(stderr(&output).contains("Delete it to go back to the default settings."));
+    assert!(!stderr(&output).contains("schedule"));
+
+    fs::remove_file(scratch.dir.join(".doq_config")).unwrap();
+    assert!(scratch.doq(&["list"]).status.success());
+}
+
+
+#[test]
+fn add_uses_the_configured_default_repeat()
+{
+    let scratch = Scratch::new("default_repeat");
+    fs::write(scratch.dir.join(".doq_config"), "default_repeat: daily\ndefault_at_least: true\n").unwrap();
+
+    assert!(scratch.doq(&["add", "dishes"]).status.success());
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "1w"]).status.success());
+    assert!(scratch.doq(&["add", "party", "--repeat", "never"]).status.success());
+
+    let schedule = fs::read_to_string(scratch.schedule()).unwrap();
+    let tasks: Vec<_> = schedule.split("- id: ").skip(1).collect();
+    assert!(tasks[0].contains("repeat: 1d") && tasks[0].contains("at_least: true"), "{}", tasks[0]);
+    assert!(tasks[1].contains("repeat: 1w") && tasks[1].contains("at_least: true"), "{}", tasks[1]);
+    assert!(tasks[2].contains("repeat: never") && tasks[2].contains("at_least: false"), "{}", tasks[2]);
+
+    fs::write(scratch.dir.join(".doq_config"), "default_repeat: often\n").unwrap();
+    let output = scratch.doq(&["list"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Invalid default_repeat 'often' in config"));
+
+    fs::remove_file(scratch.dir.join(".doq_config")).unwrap();
+    let output = scratch.doq(&["add", "vacuum"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("No --repeat given"));
+}
+
+
+#[test]
+fn config_date_format_changes_listed_dates()
+{
+    let scratch = Scratch::new("date_format");
+    fs::write(scratch.dir.join(".doq_config"), "date_format: \"%d/%m/%Y\"\n").unwrap();
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "2020-06-03"]).status.success());
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "+9d"]).status.success());
+
+    let output = String::from_utf8(scratch.doq(&["list"]).stdout).unwrap();
+    assert!(output.contains("03/06/2020"), "{}", output);
+    assert!(!output.contains("2020-06-03"));
+
+    let output = String::from_utf8(scratch.doq(&["agenda", "--days", "10"]).stdout).unwrap();
+    assert!(!output.contains("-"), "{}", output);
+    let header = output.lines().find(|line| line.contains('/')).unwrap();
+    let weekdays = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+    assert!(weekdays.iter().any(|weekday| header.starts_with(weekday)), "{}", output);
+
+    fs::write(scratch.dir.join(".doq_config"), "date_format: \"%Y-%Q\"\n").unwrap();
+    let output = scratch.doq(&["list"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Invalid date_format '%Y-%Q' in config"));
+}
+
+
+#[test]
+fn calendar_weeks_start_on_the_configured_day()
+{
+    let scratch = Scratch::new("week_start");
+    assert!(scratch.doq(&["add", "bins", "--repeat", "14d", "--on", "2017-05-03"]).status.success());
+
+    fs::write(scratch.dir.join(".doq_config"), "week_start: sun\n").unwrap();
+    let output = String::from_utf8_lossy(&scratch.doq(&["calendar", "2017-05"]).stdout).into_owned();
+    assert!(output.contains("\nSu  Mo  Tu  We  Th  Fr  Sa\n     1   2   3!  4   5   6\n"), "{}", output);
+
+    fs::write(scratch.dir.join(".doq_config"), "week_start: Wednesday\n").unwrap();
+    let output = String::from_utf8_lossy(&scratch.doq(&["calendar", "2017-05"]).stdout).into_owned();
+    assert!(output.contains("\nWe  Th  Fr  Sa  Su  Mo  Tu\n                     1   2\n 3!  4"), "{}", output);
+
+    fs::write(scratch.dir.join(".doq_config"), "week_start: someday\n").unwrap();
+    let output = scratch.doq(&["calendar", "2017-05"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Failed to parse config"));
+}
+
+
+#[test]
+fn digest_lists_only_pressing_tasks()
+{
+    let scratch = Scratch::new("digest");
+    let output = scratch.doq(&["digest"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "+3d"]).status.success());
+    assert!(scratch.doq(&["digest"]).stdout.is_empty());
+
+    assert!(scratch.doq(&["add", "trash", "--repeat", "7d", "--on", "-1d"]).status.success());
+    assert!(scratch.doq(&["add", "water plants", "--repeat", "3d", "--on", "-2d"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "today"]).status.success());
+    assert!(scratch.doq(&["add", "car", "--repeat", "1d", "--on", "-5d"]).status.success());
+    assert!(scratch.doq(&["pause", "car"]).status.success());
+
+    let output = scratch.doq(&["digest"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Overdue: water plants (2 days), trash (1 day)\nDue today: dishes\n");
+
+    assert!(scratch.doq(&["did", "trash", "water plants", "--yes"]).status.success());
+    assert_eq!(String::from_utf8_lossy(&scratch.doq(&["digest"]).stdout), "Due today: dishes\n");
+}
+
+
+#[test]
+fn heatmap_shades_days_by_completions()
+{
+    let scratch = Scratch::new("heatmap");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "7d"]).status.success());
+    assert!(scratch.doq(&["did", "dishes", "laundry", "--yes"]).status.success());
+
+    let output = String::from_utf8(scratch.doq(&["heatmap"]).stdout).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    let rows: Vec<_> = lines[1..8].iter().map(|line| &line[..2]).collect();
+    assert_eq!(rows, ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"]);
+    assert_eq!(output.matches('▒').count(), 2, "{}", output);
+    assert!(output.contains("\n2 completions in the last year\n"));
+
+    let output = String::from_utf8(scratch.doq(&["heatmap", "laundry"]).stdout).unwrap();
+    assert_eq!(output.matches('░').count(), 2, "{}", output);
+    assert!(output.contains("\n1 completion in the last year\n"));
+
+    fs::write(scratch.dir.join(".doq_config"), "week_start: sun\n").unwrap();
+    let output = String::from_utf8(scratch.doq(&["heatmap"]).stdout).unwrap();
+    assert!(output.lines().nth(1).unwrap().starts_with("Su "));
+}
+
+
+#[test]
+fn listing_can_be_grouped_by_tag()
+{
+    let scratch = Scratch::new("group_by");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "+1d", "--tag", "kitchen"]).status.success());
+    assert!(scratch.doq(&["add", "fridge", "--repeat", "1m", "--on", "-1d", "--tag", "kitchen", "--tag", "shopping"]).status.success());
+    assert!(scratch.doq(&["add", "milk", "--repeat", "7d", "--on", "+2d", "--tag", "shopping"]).status.success());
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "+30d"]).status.success());
+
+    let output = String::from_utf8(scratch.doq(&["--group-by", "tag", "--symbols"]).stdout).unwrap();
+    let names: Vec<_> = output.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line[2..].split_whitespace().next().unwrap())
+        .collect();
+    assert_eq!(names, ["Task", "(!", "===", "[kitchen]", "fridge", "dishes", "[shopping]", "fridge", "milk", "(no", "taxes", "overdue,"]);
+    assert_eq!(output.matches("Task").count(), 1);
+    assert!(output.ends_with("\n\n1 overdue, 0 due today, 3 upcoming\n"), "{}", output);
+}
+
+
+#[test]
+fn due_lists_tasks_falling_on_a_date()
+{
+    let scratch = Scratch::new("due");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "today"]).status.success());
+    assert!(scratch.doq(&["add", "bins", "--repeat", "7d", "--on", "tomorrow"]).status.success());
+    assert!(scratch.doq(&["add", "plants", "--repeat", "3d", "--on", "+2d"]).status.success());
+    assert!(scratch.doq(&["add", "party", "--repeat", "never", "--on", "+8d"]).status.success());
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "-3d"]).status.success());
+
+    let output = String::from_utf8(scratch.doq(&["due", "+8d"]).stdout).unwrap();
+    let lines: Vec<_> = output.lines().skip(1).collect();
+    assert_eq!(lines, ["    dishes", "    bins", "    plants", "    party"]);
+
+    let output = String::from_utf8(scratch.doq(&["due", "today"]).stdout).unwrap();
+    let lines: Vec<_> = output.lines().skip(1).collect();
+    assert_eq!(lines, ["    taxes (overdue)", "    dishes"]);
+
+    let output = String::from_utf8(scratch.doq(&["due", "2000-01-01"]).stdout).unwrap();
+    assert_eq!(output, "Nothing due on 2000-01-01\n");
+}
+
+
+#[test]
+fn reset_overdue_moves_only_overdue_tasks()
+{
+    let scratch = Scratch::new("reset_overdue");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--done-on", "-4d"]).status.success());
+    assert!(scratch.doq(&["add", "plants", "--repeat", "3d", "--on", "-10d"]).status.success());
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "7d", "--on", "today"]).status.success());
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "+30d"]).status.success());
+    let before = fs::read_to_string(scratch.schedule()).unwrap();
+
+    let output = scratch.doq(&["reset-overdue"]);
+    assert!(stderr(&output).contains("Cancelling"));
+    assert_eq!(fs::read_to_string(scratch.schedule()).unwrap(), before);
+
+    let output = scratch.doq(&["reset-overdue", "--yes"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Moved 2 tasks to "));
+
+    let output = String::from_utf8(scratch.doq(&["--porcelain"]).stdout).unwrap();
+    let deltas: Vec<_> = output.lines().map(|line| { let fields: Vec<_> = line.split('\t').collect(); (fields[0].to_owned(), fields[4].to_owned()) }).collect();
+    assert_eq!(deltas, [("dishes".to_owned(), "0".to_owned()), ("laundry".to_owned(), "0".to_owned()), ("plants".to_owned(), "0".to_owned()), ("taxes".to_owned(), "30".to_owned())]);
+
+    let schedule = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(schedule.contains("repeat: 3d"));
+    assert_eq!(schedule.matches("date_completed: ~").count(), 3);
+
+    let output = scratch.doq(&["reset-overdue", "--to", "+2d"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No overdue tasks"));
+}
+
+
+#[test]
+fn repeat_until_retires_the_task_after_its_last_occurrence()
+{
+    let scratch = Scratch::new("repeat_until");
+    assert!(scratch.doq(&["add", "physio", "--repeat", "weekly", "--on", "-21d", "--until", "-7d"]).status.success());
+    assert!(!scratch.doq(&["add", "party", "--repeat", "never", "--until", "+7d"]).status.success());
+
+    assert!(scratch.doq(&["did", "physio", "--on", "-21d", "--yes"]).status.success());
+    assert!(scratch.doq(&["did", "physio", "--on", "-14d", "--yes"]).status.success());
+    assert!(String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).contains("physio"));
+
+    assert!(scratch.doq(&["did", "physio", "--on", "-7d", "--yes"]).status.success());
+    assert!(!String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).contains("physio"));
+    let schedule = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(schedule.split("archived:").nth(1).unwrap().contains("name: physio"));
+
+    assert!(scratch.doq(&["add", "gym", "--repeat", "1d", "--until", "today"]).status.success());
+    assert!(scratch.doq(&["edit", "gym", "--until", "never"]).status.success());
+    assert!(scratch.doq(&["did", "gym", "--yes"]).status.success());
+    assert!(String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).contains("gym"));
+}
+
+
+#[test]
+fn undo_gives_back_a_repetition()
+{
+    let scratch = Scratch::new("undo_repeat_count");
+    assert!(scratch.doq(&["add", "physio", "--repeat", "1d", "--on", "2017-05-01", "--times", "3"]).status.success());
+    let before = fs::read(scratch.schedule()).unwrap();
+
+    assert!(scratch.doq(&["did", "physio", "--on", "2017-05-01", "--yes"]).status.success());
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("repeat_count: 2"));
+
+    assert!(scratch.doq(&["undo", "physio"]).status.success());
+    assert_eq!(fs::read(scratch.schedule()).unwrap(), before);
+}
+
+
+#[test]
+fn add_rejects_done_on_when_no_repeats_would_be_left()
+{
+    let scratch = Scratch::new("done_on_finished");
+
+    let output = scratch.doq(&["add", "physio", "--repeat", "1w", "--times", "1", "--done-on", "2017-05-01"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("no repeats left"));
+
+    let output = scratch.doq(&["add", "gym", "--repeat", "1w", "--until", "2017-05-05", "--done-on", "2017-05-01"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("no repeats left"));
+
+    assert!(scratch.doq(&["add", "yoga", "--repeat", "1w", "--times", "2", "--done-on", "2017-05-01"]).status.success());
+    assert!(String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).contains(" 1w (1 left) "));
+}
+
+
+#[test]
+fn repeat_count_retires_the_task_after_its_last_completion()
+{
+    let scratch = Scratch::new("repeat_count");
+    assert!(scratch.doq(&["add", "physio", "--repeat", "1d", "--times", "2"]).status.success());
+    assert!(!scratch.doq(&["add", "gym", "--repeat", "1d", "--times", "0"]).status.success());
+    assert!(!scratch.doq(&["add", "party", "--repeat", "never", "--times", "2"]).status.success());
+
+    assert!(String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).contains(" 1d (2 left) "));
+
+    assert!(scratch.doq(&["did", "physio", "--yes"]).status.success());
+    assert!(String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).contains(" 1d (1 left) "));
+
+    assert!(scratch.doq(&["did", "physio", "--yes", "--force"]).status.success());
+    assert!(!String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).contains("physio"));
+    let schedule = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(schedule.split("archived:").nth(1).unwrap().contains("name: physio"));
+}
+
+
+#[test]
+fn intervals_show_how_often_tasks_really_happen()
+{
+    let scratch = Scratch::new("intervals");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "7d", "--on", "2017-05-01"]).status.success());
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "2017-05-01"]).status.success());
+    for &date in &["2017-05-01", "2017-05-10", "2017-05-18", "2017-05-28"]
+    {
+        assert!(scratch.doq(&["did", "dishes", "--on", date, "-y"]).status.success());
+    }
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["list", "--intervals"]).stdout).into_owned();
+    assert!(output.contains(" 7d (mean 9.0, median 9.0 days) "), "{}", output);
+    assert!(output.contains(" 1y (—) "), "{}", output);
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).into_owned();
+    assert!(!output.contains("mean"));
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["stats", "dishes", "--intervals"]).stdout).into_owned();
+    assert!(output.contains("    Intervals:        mean 9.0, median 9.0 days (repeats 7d)\n"), "{}", output);
+}
+
+
+#[test]
+fn stats_read_due_dates_from_mixed_history()
+{
+    let scratch = Scratch::new("mixed_history");
+    fs::write(scratch.schedule(), "tasks:\n  - name: dishes\n    date_completed: \"2017-05-09\"\n    date_due: \"2017-05-15\"\n    repeat: 7d\n    at_least: false\n    history:\n      - \"2017-05-01\"\n      - date: \"2017-05-09\"\n        was_due: \"2017-05-08\"\n").unwrap();
+
+    assert!(scratch.doq(&["did", "dishes", "--on", "2017-05-14", "-y"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["stats", "dishes"]).stdout).into_owned();
+    assert!(output.contains("Completions:      3"), "{}", output);
+    assert!(output.contains("On time:          1"));
+    assert!(output.contains("Late:             1"));
+
+    let schedule = fs::read_to_string(scratch.schedule()).unwrap();
+    assert!(schedule.contains("- date: \"2017-05-14\"\n        was_due: \"2017-05-15\""), "{}", schedule);
+    assert!(schedule.contains("- date: \"2017-05-01\"\n        was_due: ~"));
+}
+
+
+#[test]
+fn config_horizon_limits_the_default_listing()
+{
+    let scratch = Scratch::new("horizon");
+    fs::write(scratch.dir.join(".doq_config"), "default_horizon_days: 7\n").unwrap();
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "1y", "--on", "-30d"]).status.success());
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "+7d"]).status.success());
+    assert!(scratch.doq(&["add", "dentist", "--repeat", "6m", "--on", "+30d"]).status.success());
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).into_owned();
+    assert!(output.contains("taxes") && output.contains("dishes"));
+    assert!(!output.contains("dentist"));
+    assert!(!String::from_utf8_lossy(&scratch.doq(&[]).stdout).contains("dentist"));
+
+    assert!(String::from_utf8_lossy(&scratch.doq(&["list", "--all"]).stdout).contains("dentist"));
+    assert!(String::from_utf8_lossy(&scratch.doq(&["list", "--due-within", "30"]).stdout).contains("dentist"));
+}
+
+
+#[test]
+fn projects_group_tasks_and_scope_the_listing()
+{
+    let scratch = Scratch::new("projects");
+    assert_eq!(String::from_utf8_lossy(&scratch.doq(&["projects"]).stdout), "No projects\n");
+
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "-2d", "--project", "Kitchen"]).status.success());
+    assert!(scratch.doq(&["add", "fridge", "--repeat", "1m", "--project", "Kitchen"]).status.success());
+    assert!(scratch.doq(&["add", "tyres", "--repeat", "1m", "--on", "+5d", "--project", "Car"]).status.success());
+    assert!(scratch.doq(&["add", "plants", "--repeat", "3d"]).status.success());
+
+    assert_eq!(
+        String::from_utf8_lossy(&scratch.doq(&["projects"]).stdout),
+        "Car: 1 task, none overdue\nKitchen: 2 tasks, 1 overdue\n");
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["--project", "Kitchen"]).stdout).into_owned();
+    assert!(output.contains("dishes") && output.contains("fridge"));
+    assert!(!output.contains("tyres") && !output.contains("plants"));
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["list", "--project", "Car"]).stdout).into_owned();
+    assert!(output.contains("tyres") && !output.contains("dishes"));
+
+    assert!(scratch.doq(&["edit", "tyres", "--project", ""]).status.success());
+    assert_eq!(String::from_utf8_lossy(&scratch.doq(&["projects"]).stdout), "Kitchen: 2 tasks, 1 overdue\n");
+}
+
+
+#[test]
+fn blocked_tasks_are_not_counted_as_overdue()
+{
+    let scratch = Scratch::new("blocked_summary");
+    assert!(scratch.doq(&["add", "buy filter", "--repeat", "1m", "--on", "+3d"]).status.success());
+    assert!(scratch.doq(&["add", "replace filter", "--repeat", "1m", "--on", "-2d", "--depends-on", "buy"]).status.success());
+
+    let output = scratch.doq(&["--check"]);
+    assert_eq!(output.status.code(), Some(0));
+    let output = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.lines().last(), Some("0 overdue, 0 due today, 1 upcoming, 1 waiting"));
+
+    assert!(scratch.doq(&["edit", "replace filter", "--depends-on", ""]).status.success());
+    let output = scratch.doq(&["--check"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.lines().last(), Some("1 overdue, 0 due today, 1 upcoming"));
+}
+
+
+#[test]
+fn dependent_tasks_wait_for_their_prerequisite()
+{
+    let scratch = Scratch::new("depends");
+    assert!(scratch.doq(&["add", "buy filter", "--repeat", "1m", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq(&["add", "replace filter", "--repeat", "1m", "--on", "2000-01-01", "--depends-on", "buy"]).status.success());
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("depends_on: buy filter"));
+
+    let output = String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).into_owned();
+    assert!(output.contains("(Waiting on buy filter)"));
+
+    let output = scratch.doq(&["did", "replace filter", "--on", "2000-01-02", "-y"]);
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("'replace filter' is waiting on 'buy filter'"));
+
+    assert!(scratch.doq(&["did", "buy filter", "--on", "2000-01-03", "-y"]).status.success());
+    let output = String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).into_owned();
+    assert!(!output.contains("Waiting on"));
+
+    let output = scratch.doq(&["did", "replace filter", "--on", "2000-01-04", "-y"]);
+    assert!(!stderr(&output).contains("waiting on"));
+    assert!(String::from_utf8_lossy(&scratch.doq(&["list"]).stdout).contains("(Waiting on buy filter)"));
+
+    let output = scratch.doq(&["edit", "buy filter", "--depends-on", "replace"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("depends on itself"));
+
+    assert!(scratch.doq(&["edit", "buy filter", "--rename", "order filter"]).status.success());
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("depends_on: order filter"));
+}
+
+
+#[test]
+fn watch_redraws_when_the_schedule_changes()
+{
+    use std::io::Read;
+
+    let scratch = Scratch::new("watch");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d"]).status.success());
+
+    let mut child = scratch.command(&["watch", "--interval", "1"]).stdout(Stdio::piped()).spawn().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "7d"]).status.success());
+    std::thread::sleep(std::time::Duration::from_millis(2000));
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let mut output = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut output).unwrap();
+    let screens: Vec<_> = output.split("\x1b[2J\x1b[H").skip(1).collect();
+    assert_eq!(screens.len(), 2, "{:?}", output);
+    assert!(screens[0].contains("dishes") && !screens[0].contains("laundry"));
+    assert!(screens[1].contains("dishes") && screens[1].contains("laundry"));
+}
+
+
+#[cfg(feature = "notify")]
+#[test]
+fn notify_prints_the_digest_when_headless()
+{
+    let scratch = Scratch::new("notify");
+    assert!(scratch.doq(&["add", "dishes", "--repeat", "1d", "--on", "-2d"]).status.success());
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "7d", "--on", "+3d"]).status.success());
+
+    for args in &[&["notify"][..], &["notify", "--individual"][..]]
+    {
+        let output = scratch.command(args)
+            .env("DBUS_SESSION_BUS_ADDRESS", "unix:path=/nonexistent")
+            .env_remove("DISPLAY")
+            .env_remove("WAYLAND_DISPLAY")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "Overdue: dishes (2 days)\n");
+    }
+}
+
+
+#[test]
+fn did_runs_the_on_complete_hook()
+{
+    let scratch = Scratch::new("on-complete");
+    let log = scratch.dir.join("hook.log");
+    let config = format!("on_complete: \"printf '%s|%s|%s|%s\\\\n' {{name}} {{date}} {{due}} {{next}} >> {}\"\n", log.display());
+    fs::write(scratch.dir.join(".doq_config"), config).unwrap();
+
+    assert!(scratch.doq(&["add", "mum's plants", "--repeat", "3d", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq(&["add", "taxes", "--repeat", "never", "--on", "2000-01-01"]).status.success());
+    assert!(scratch.doq(&["did", "plants", "taxes", "--on", "2000-01-02", "-y"]).status.success());
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        "mum's plants|2000-01-02|2000-01-01|2000-01-04\ntaxes|2000-01-02|2000-01-01|never\n");
+
+    fs::write(scratch.dir.join(".doq_config"), "on_complete: \"exit 3\"\n").unwrap();
+    let output = scratch.doq(&["did", "plants", "--on", "2000-01-04", "-y"]);
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("on_complete hook for 'mum's plants' failed"));
+    assert!(fs::read_to_string(scratch.schedule()).unwrap().contains("date_completed: \"2000-01-04\""));
+}
+
+
+#[test]
+fn git_autocommit_commits_each_save()
+{
+    let scratch = Scratch::new("git-autocommit");
+    fs::write(scratch.dir.join(".doq_config"), "git_autocommit: true\n").unwrap();
+
+    // Outside a repository, saving works as normal
+    let output = scratch.doq(&["add", "dishes", "--repeat", "1d"]);
+    assert!(output.status.success());
+    assert!(stderr(&output).is_empty(), "{}", stderr(&output));
+
+    let git = |args: &[&str]| Command::new("git").current_dir(&scratch.dir).args(args).env("HOME", &scratch.dir).output().unwrap();
+    assert!(git(&["init", "-q"]).status.success());
+    assert!(git(&["config", "user.name", "doq"]).status.success());
+    assert!(git(&["config", "user.email", "doq@example.com"]).status.success());
+
+    assert!(scratch.doq(&["add", "laundry", "--repeat", "7d"]).status.success());
+    assert!(scratch.doq(&["did", "dishes", "-y"]).status.success());
+    assert!(scratch.doq(&["list"]).status.success());
+    assert!(scratch.doq(&["--dry-run", "did", "laundry", "-y"]).status.success());
+
+    let log = String::from_utf8(git(&["log", "--format=%s"]).stdout).unwrap();
+    assert_eq!(log, "doq: did\ndoq: add\n");
+    assert!(String::from_utf8(git(&["show", "--stat", "HEAD"]).stdout).unwrap().contains("schedule.yaml"));
+}
+
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_schedules_need_the_passphrase()
+{
+    let scratch = Scratch::new("encryption");
+    let doq = |args: &[&str], passphrase: &str| scratch.command(args).env("DOQ_PASSPHRASE", passphrase).output().unwrap();
+
+    assert!(scratch.doq(&["add", "diary", "--repeat", "1d"]).status.success());
+    fs::write(scratch.dir.join(".doq_config"), "encrypt: true\n").unwrap();
+
+    let output = doq(&["add", "therapy", "--repeat", "7d"], "hunter2");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let contents = fs::read(scratch.schedule()).unwrap();
+    assert!(contents.starts_with(b"doq-encrypted-v1\n"));
+    assert!(!String::from_utf8_lossy(&contents).contains("therapy"));
+    assert!(!scratch.dir.join("schedule.yaml.bak").exists());
+
+    // Files stay encrypted once they are, even without the config
+    fs::remove_file(scratch.dir.join(".doq_config")).unwrap();
+    assert!(doq(&["did", "diary", "-y"], "hunter2").status.success());
+    let output = String::from_utf8(doq(&["--porcelain"], "hunter2").stdout).unwrap();
+    assert!(output.contains("diary") && output.contains("therapy"));
+    assert!(fs::read(scratch.schedule()).unwrap().starts_with(b"doq-encrypted-v1\n"));
+
+    // Completion can't ask for the passphrase, so it offers nothing
+    let output = scratch.doq(&["__complete", "di"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = doq(&["list"], "hunter3");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Is the passphrase right?"));
+}
+
+
+#[cfg(not(feature = "encryption"))]
+#[test]
+fn encrypted_schedules_need_the_encryption_feature()
+{
+    let scratch = Scratch::new("no-encryption");
+    fs::write(scratch.schedule(), b"doq-encrypted-v1\n\x00\x01\x02").unwrap();
+
+    let output = scratch.doq(&["list"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("built without the encryption feature"));
+
+    // Completion stays quiet rather than failing on every Tab press
+    let output = scratch.doq(&["__complete", "di"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+
+    fs::remove_file(scratch.schedule()).unwrap();
+    fs::write(scratch.dir.join(".doq_config"), "encrypt: true\n").unwrap();
+    let output = scratch.doq(&["list"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("built without the encryption feature"));
+}